@@ -0,0 +1,62 @@
+const R = require('ramda');
+const fs = require('fs').promises;
+const { resolveInside, safeSegment } = require('./paths.js');
+const state = require('./state.js');
+
+const extensions = {
+  'image/gif': 'gif',
+  'image/jpeg': 'jpg',
+  'image/png': 'png',
+  'image/svg+xml': 'svg',
+};
+const mimeTypes = R.invertObj(extensions);
+const dataUrl = /^data:([^;,]+);base64,(.*)$/s;
+
+/* Move the base64 data URL assets of a canvas workpad into binary files
+ * under `${dir}/${assetDir}`, leaving a `file:` path in each asset's value.
+ * Assets with an unknown mime type are left inline. Asset ids come from the
+ * workpad, so they are made safe as file names first.
+ */
+async function extractAssets(workpad, dir, assetDir) {
+  const assets = R.path(['attributes', 'assets'], workpad);
+  if (!assets) return workpad;
+
+  const extracted = {};
+  for (const [id, asset] of Object.entries(assets)) {
+    const match = typeof asset.value === 'string' && asset.value.match(dataUrl);
+    if (match && extensions[match[1]]) {
+      const file = `${assetDir}/${safeSegment(id)}.${extensions[match[1]]}`;
+      await fs.mkdir(resolveInside(dir, assetDir), { recursive: true });
+      await state.writeAtomic(
+        resolveInside(dir, file),
+        Buffer.from(match[2], 'base64')
+      );
+      extracted[id] = { ...asset, value: `file:${file}` };
+    } else {
+      extracted[id] = asset;
+    }
+  }
+  return R.assocPath(['attributes', 'assets'], extracted, workpad);
+}
+
+// Read extracted asset files back into data URLs, relative to dir and only
+// from inside it, so a crafted workpad cannot pull in any other local file
+async function inlineAssets(workpad, dir) {
+  const assets = R.path(['attributes', 'assets'], workpad);
+  if (!assets) return workpad;
+
+  const inlined = {};
+  for (const [id, asset] of Object.entries(assets)) {
+    if (typeof asset.value === 'string' && asset.value.startsWith('file:')) {
+      const file = asset.value.slice('file:'.length);
+      const mimeType = mimeTypes[file.split('.').pop()];
+      const data = await fs.readFile(resolveInside(dir, file), 'base64');
+      inlined[id] = { ...asset, value: `data:${mimeType};base64,${data}` };
+    } else {
+      inlined[id] = asset;
+    }
+  }
+  return R.assocPath(['attributes', 'assets'], inlined, workpad);
+}
+
+module.exports = { extractAssets, inlineAssets };
@@ -0,0 +1,56 @@
+const R = require('ramda');
+
+const isObject = (value) =>
+  value !== null && typeof value === 'object' && !Array.isArray(value);
+
+// Recursively list the paths that differ between two JSON values
+function diffFields(before, after, path = []) {
+  if (R.equals(before, after)) return [];
+  if (!isObject(before) || !isObject(after)) {
+    return [{ change: 'changed', path }];
+  }
+  return R.chain((key) => {
+    if (!(key in before)) return [{ change: 'added', path: [...path, key] }];
+    if (!(key in after)) return [{ change: 'removed', path: [...path, key] }];
+    return diffFields(before[key], after[key], [...path, key]);
+  }, R.union(Object.keys(before), Object.keys(after)));
+}
+
+// Dashboard panels are stored as a JSON string, index them by panelIndex
+function panelIndexes(obj) {
+  try {
+    const json = R.pathOr('[]', ['attributes', 'panelsJSON'], obj);
+    const panels = typeof json === 'string' ? JSON.parse(json) : json;
+    return panels.map((panel) => String(panel.panelIndex));
+  } catch (err) {
+    return [];
+  }
+}
+
+const referenceKeys = (obj) =>
+  (obj.references || []).map((ref) => `${ref.type}/${ref.id}`);
+
+/* Summarize the changes between two versions of a saved object.
+ * References are compared as a set, so reordering them is not a change.
+ */
+function diffObjects(before, after) {
+  const fields = diffFields(
+    R.omit(['references'], before),
+    R.omit(['references'], after)
+  );
+  return {
+    fields: fields.map(({ change, path }) => ({
+      change,
+      path: path.join('.'),
+    })),
+    panelsAdded: R.difference(panelIndexes(after), panelIndexes(before)),
+    panelsRemoved: R.difference(panelIndexes(before), panelIndexes(after)),
+    referencesAdded: R.difference(referenceKeys(after), referenceKeys(before)),
+    referencesRemoved: R.difference(
+      referenceKeys(before),
+      referenceKeys(after)
+    ),
+  };
+}
+
+module.exports = { diffFields, diffObjects };
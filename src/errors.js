@@ -0,0 +1,40 @@
+/* Errors with a code to match on, rather than the message. Kibana API
+ * failures also carry the response status and body.
+ *   AUTH_FAILED      401 or 403 from Kibana
+ *   NOT_FOUND        404 from Kibana, or a missing local file or snapshot
+ *   CONFLICT         409 from Kibana
+ *   API_ERROR        any other error response from Kibana
+ *   REMOTE_NOT_FOUND a --remote that is not in remotes.json
+ *   INVALID_BUNDLE   a bundle that does not match its manifest
+ *   NO_KEYRING       no supported OS keyring to save credentials in
+ *   INVALID_PLUGIN   a plugin module without pull or push functions
+ *   LOCKED           another kibob holds the lock on the state directory
+ *   DRIFTED          objects changed in Kibana since the lockfile
+ *   UNSAFE_PATH      a path from an object that leads outside its directory
+ *   UNCOMMITTED      uncommitted changes in a tree about to be tagged
+ *   MISSING_SECRET   a ${NAME} placeholder with no value to fill it
+ */
+class KibobError extends Error {
+  constructor(code, message, details = {}) {
+    super(message);
+    this.name = 'KibobError';
+    this.code = code;
+    Object.assign(this, details);
+  }
+}
+
+const statusCodes = {
+  401: 'AUTH_FAILED',
+  403: 'AUTH_FAILED',
+  404: 'NOT_FOUND',
+  409: 'CONFLICT',
+};
+
+// Error for a Kibana API response that was not successful
+function apiError(status, statusText, body) {
+  const code = statusCodes[status] || 'API_ERROR';
+  const message = `${status} ${statusText} Error: ${JSON.stringify(body)}`;
+  return new KibobError(code, message, { status, body });
+}
+
+module.exports = { KibobError, apiError };
@@ -0,0 +1,129 @@
+const R = require('ramda');
+
+const key = (obj) => `${obj.type}/${obj.id}`;
+
+/* The reference graph of a set of saved objects: a node per object and per
+ * referenced object, and an edge from each object to every object it
+ * references. Nodes of referenced objects missing from the set have no title.
+ */
+function buildGraph(objects) {
+  const nodes = {};
+  const edges = [];
+  for (const obj of objects) {
+    nodes[key(obj)] = { type: obj.type, id: obj.id, title: obj.title };
+  }
+  for (const obj of objects) {
+    for (const ref of obj.references || []) {
+      nodes[key(ref)] = nodes[key(ref)] || { type: ref.type, id: ref.id };
+      edges.push({ from: key(obj), to: key(ref) });
+    }
+  }
+  return { nodes, edges: R.uniqBy((edge) => `${edge.from} ${edge.to}`, edges) };
+}
+
+// Nodes reachable from root following edges in one direction
+function reachable(edges, root, from, to) {
+  const seen = new Set([root]);
+  const queue = [root];
+  while (queue.length > 0) {
+    const node = queue.shift();
+    for (const edge of edges) {
+      if (edge[from] === node && !seen.has(edge[to])) {
+        seen.add(edge[to]);
+        queue.push(edge[to]);
+      }
+    }
+  }
+  return seen;
+}
+
+/* The part of a graph connected to root: everything it references, and
+ * everything that references it, i.e. what a change to root can break.
+ */
+function subgraph(graph, root) {
+  const keep = new Set([
+    ...reachable(graph.edges, root, 'from', 'to'),
+    ...reachable(graph.edges, root, 'to', 'from'),
+  ]);
+  return {
+    nodes: R.pickBy((node, id) => keep.has(id), graph.nodes),
+    edges: graph.edges.filter(
+      (edge) => keep.has(edge.from) && keep.has(edge.to)
+    ),
+  };
+}
+
+const label = (node) => `${node.type}: ${node.title || node.id}`;
+const quote = (text) => `"${String(text).replace(/"/g, '\\"')}"`;
+
+// Graphviz DOT source for a graph
+function toDot(graph) {
+  const lines = ['digraph references {', '  rankdir=LR;'];
+  for (const [id, node] of Object.entries(graph.nodes)) {
+    lines.push(`  ${quote(id)} [label=${quote(label(node))}];`);
+  }
+  for (const { from, to } of graph.edges) {
+    lines.push(`  ${quote(from)} -> ${quote(to)};`);
+  }
+  lines.push('}');
+  return lines.join('\n') + '\n';
+}
+
+// Mermaid flowchart source for a graph, node ids are numbered
+function toMermaid(graph) {
+  const ids = Object.keys(graph.nodes);
+  const name = (id) => `n${ids.indexOf(id)}`;
+  const lines = ['flowchart LR'];
+  for (const id of ids) {
+    const text = label(graph.nodes[id]).replace(/"/g, '#quot;');
+    lines.push(`  ${name(id)}["${text}"]`);
+  }
+  for (const { from, to } of graph.edges) {
+    lines.push(`  ${name(from)} --> ${name(to)}`);
+  }
+  return lines.join('\n') + '\n';
+}
+
+/* Order objects so each comes after the objects it references, e.g. data
+ * views before visualizations before dashboards, so Kibana never imports a
+ * reference before its target. Ties are broken by type and id, and
+ * reference cycles are cut where they are found.
+ */
+function sortByReferences(objects) {
+  const byKey = R.indexBy(key, objects);
+  const state = {};
+  const sorted = [];
+  const visit = (obj) => {
+    if (state[key(obj)]) return;
+    state[key(obj)] = 'visiting';
+    for (const ref of obj.references || []) {
+      if (byKey[key(ref)]) visit(byKey[key(ref)]);
+    }
+    state[key(obj)] = 'done';
+    sorted.push(obj);
+  };
+  R.sortBy(key, objects).forEach(visit);
+  return sorted;
+}
+
+/* Tiers of types that only reference types in earlier tiers: data views and
+ * tags, then saved searches, then visualizations and every type not listed,
+ * then dashboards. Files too large to sort in memory are written and imported
+ * a tier at a time instead, which keeps most references in order.
+ */
+const typeTiers = { 'index-pattern': 0, tag: 0, search: 1, dashboard: 3 };
+const tierCount = 4;
+
+function typeTier(type) {
+  return type in typeTiers ? typeTiers[type] : 2;
+}
+
+module.exports = {
+  buildGraph,
+  sortByReferences,
+  subgraph,
+  tierCount,
+  toDot,
+  toMermaid,
+  typeTier,
+};
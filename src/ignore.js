@@ -0,0 +1,99 @@
+const fs = require('fs').promises;
+
+const ignoreFile = '.kibobignore';
+
+const starter = `# Files in this directory that kibob leaves out of bundle, import, watch,
+# status and the other commands that read it, in .gitignore syntax.
+#
+# scratch/
+# *.wip.json
+# !shared.wip.json
+`;
+
+const escapeRegExp = (text) => text.replace(/[.*+?^${}()|[\]\\]/g, '\\$&');
+
+/* Convert a .gitignore pattern into a RegExp over paths relative to the
+ * directory. A pattern with a slash before its end is anchored to the
+ * directory, others match at any depth, and a trailing slash only matches
+ * what is inside a directory.
+ */
+function patternRegExp(pattern) {
+  const dirOnly = pattern.endsWith('/');
+  if (dirOnly) pattern = pattern.slice(0, -1);
+  const anchored = pattern.includes('/');
+  if (pattern.startsWith('/')) pattern = pattern.slice(1);
+
+  let source = '';
+  for (let i = 0; i < pattern.length; i++) {
+    const char = pattern[i];
+    if (char === '*' && pattern[i + 1] === '*') {
+      // **/ matches any number of directories, any other ** anything
+      i++;
+      if (pattern[i + 1] === '/') {
+        i++;
+        source += '(?:.*/)?';
+      } else {
+        source += '.*';
+      }
+    } else if (char === '*') {
+      source += '[^/]*';
+    } else if (char === '?') {
+      source += '[^/]';
+    } else if (char === '[' && pattern.indexOf(']', i + 2) > i) {
+      const end = pattern.indexOf(']', i + 2);
+      source += pattern.slice(i, end + 1).replace(/^\[!/, '[^');
+      i = end;
+    } else if (char === '\\' && i + 1 < pattern.length) {
+      i++;
+      source += escapeRegExp(pattern[i]);
+    } else {
+      source += escapeRegExp(char);
+    }
+  }
+
+  const prefix = anchored ? '^' : '^(?:.*/)?';
+  const suffix = dirOnly ? '/.*$' : '(?:/.*)?$';
+  return new RegExp(prefix + source + suffix);
+}
+
+/* Parse the text of a .kibobignore file into a function that tells whether
+ * a relative path is ignored. As in .gitignore, the last matching pattern
+ * decides, and a pattern starting with ! brings a path back.
+ */
+function parseIgnore(text) {
+  const rules = text
+    .split(/\r?\n/)
+    .map((line) => line.replace(/(?<!\\)\s+$/, ''))
+    .filter((line) => line && !line.startsWith('#'))
+    .map((line) => {
+      const negate = line.startsWith('!');
+      const pattern = negate ? line.slice(1) : line.replace(/^\\([#!])/, '$1');
+      return { negate, regex: patternRegExp(pattern) };
+    });
+  return (name) =>
+    rules.reduce(
+      (ignored, { negate, regex }) => (regex.test(name) ? !negate : ignored),
+      false
+    );
+}
+
+// The ignore function for a directory, ignoring nothing without a file
+async function readIgnore(dir) {
+  try {
+    return parseIgnore(await fs.readFile(`${dir}/${ignoreFile}`, 'utf8'));
+  } catch (err) {
+    if (err.code === 'ENOENT') return () => false;
+    throw err;
+  }
+}
+
+// Write a commented .kibobignore to a directory, unless it already has one
+async function writeStarterIgnore(dir) {
+  try {
+    await fs.writeFile(`${dir}/${ignoreFile}`, starter, { flag: 'wx' });
+  } catch (err) {
+    if (err.code !== 'EEXIST') throw err;
+  }
+}
+
+module.exports = { ignoreFile, parseIgnore, readIgnore, writeStarterIgnore };
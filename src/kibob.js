@@ -1,15 +1,69 @@
 #!/usr/bin/env node
 const R = require('ramda');
 const fs = require('fs').promises;
+const { createReadStream, createWriteStream } = require('fs');
+const crypto = require('crypto');
+const { once } = require('events');
+const readline = require('readline');
 const fetch = require('node-fetch');
 const FormData = require('form-data');
+const HttpsProxyAgent = require('https-proxy-agent');
+const http = require('http');
+const https = require('https');
+const os = require('os');
 const path = require('path');
 const yargs = require('yargs');
+const { format } = require('winston');
+const { execFile, execFileSync, spawn } = require('child_process');
+const { promisify } = require('util');
+
+const canvas = require('./canvas.js');
+const diff = require('./diff.js');
+const { KibobError, apiError } = require('./errors.js');
+const graph = require('./graph.js');
+const ignore = require('./ignore.js');
+const markdown = require('./markdown.js');
+const { resolveInside, safeSegment } = require('./paths.js');
+const plugins = require('./plugins.js');
+const state = require('./state.js');
+const transforms = require('./transforms.js');
+const { orphanedFiles, validateFiles } = require('./validate.js');
 
 const logger = require('./logger.js').label('kibob');
+const logFormat = logger.format;
+
+const defaultTypes = [
+  'index-pattern',
+  'search',
+  'query',
+  'visualization',
+  'lens',
+  'dashboard',
+  'canvas-workpad',
+  'map',
+  'tag',
+];
+
+// Fields that change on every save and only cause version control conflicts
+const volatileFields = ['version', 'updated_at'];
+
+const withoutVersion = R.omit(volatileFields);
 
-// Configures command-line arguments
-const argv = yargs
+// Exit codes, the first failure of a command decides which one it exits with
+const exitCodes = {
+  failure: 1,
+  usage: 2,
+  auth: 3,
+  partial: 4,
+  invalid: 5,
+  network: 6,
+};
+
+// Case settings that can be written back through the configure API
+const caseSettings = ['closure_type', 'connector', 'customFields', 'templates'];
+
+// Configures command-line arguments, parsed at the end when run as a script
+const cli = yargs
   .command(
     'export',
     'Export saved obejcts from Kibana',
@@ -36,12 +90,144 @@ const argv = yargs
         description: 'Array of types to export',
         type: 'string',
         array: true,
-        default: ['index-pattern', 'visualization', 'lens', 'dashboard', 'canvas-workpad'],
+        default: defaultTypes,
+      },
+      objects: {
+        description:
+          'Export only these objects, as type=id pairs, instead of searching',
+        type: 'string',
+        array: true,
+      },
+      references: {
+        description: 'With --objects, also export everything they reference',
+        type: 'boolean',
+        default: true,
+      },
+      drop: {
+        description: 'Fields to strip from exported objects, * matches any',
+        type: 'string',
+        array: true,
+        default: volatileFields,
+      },
+      normalize: {
+        description: 'Sort object keys and references for stable diffs',
+        type: 'boolean',
+        default: true,
+      },
+      values: {
+        description: 'JSON file of values to turn back into {{ name }}',
+        type: 'string',
+      },
+      template: {
+        description: 'Turn --values back into {{ name }} placeholders',
+        type: 'boolean',
+        default: false,
+      },
+      interactive: {
+        alias: 'i',
+        description: 'Pick the objects to export from a searchable list',
+        type: 'boolean',
+        default: false,
+      },
+      lock: {
+        description: 'Lockfile of object hashes, use --no-lock to skip it',
+        type: 'string',
+        default: 'kibob.lock',
+      },
+      'short-urls': {
+        description: 'File to save the short URLs that objects link to',
+        type: 'string',
+      },
+    },
+    async (argv) => {
+      setLogger(argv);
+      if (argv.interactive) {
+        argv.objects = await pickObjects(argv);
+        if (argv.objects.length === 0) return;
+      }
+      const objects = argv.objects
+        ? await exportObjects(argv)
+        : await findObjects(argv);
+      await updateLock(argv, objects || []);
+      if (objects) await state.recordPull(lockTarget(argv), objects.length);
+      if (argv.shortUrls) await exportShortUrls(argv, objects || []);
+      report(argv, await saveObjects(argv, objects));
+    }
+  )
+  .command(
+    'status',
+    'Compare a directory of .json files against Kibana',
+    {
+      dir: {
+        alias: 'd',
+        description: 'Directory of unbundled .json files',
+        type: 'string',
+        default: 'saved_objects',
+      },
+      url: {
+        alias: 'u',
+        description: 'The connection URL for the Kibana server',
+        type: 'string',
+        default: 'http://localhost:5601',
+      },
+      search: {
+        alias: 's',
+        description: 'Search term to find objects by',
+      },
+      types: {
+        alias: 't',
+        description: 'Array of types to compare',
+        type: 'string',
+        array: true,
+        default: defaultTypes,
+      },
+      exact: {
+        description: 'Count moving and resizing dashboard panels as changes',
+        type: 'boolean',
+        default: false,
+      },
+    },
+    async (argv) => {
+      setLogger(argv);
+      report(argv, await objectStatus(argv), printStatus);
+    }
+  )
+  .command(
+    'audit',
+    'Report how the objects in Kibana compare to a directory, as Markdown',
+    {
+      dir: {
+        alias: 'd',
+        description: 'Directory of unbundled .json files',
+        type: 'string',
+        default: 'saved_objects',
+      },
+      url: {
+        alias: 'u',
+        description: 'The connection URL for the Kibana server',
+        type: 'string',
+        default: 'http://localhost:5601',
+      },
+      search: {
+        alias: 's',
+        description: 'Search term to find objects by',
+      },
+      types: {
+        alias: 't',
+        description: 'Array of types to audit',
+        type: 'string',
+        array: true,
+        default: defaultTypes,
+      },
+      exact: {
+        description: 'Count moving and resizing dashboard panels as changes',
+        type: 'boolean',
+        default: false,
       },
     },
     async (argv) => {
       setLogger(argv);
-      saveObjects(argv.file, await findObjects(argv));
+      report(argv, await auditObjects(argv), printAudit);
     }
   )
   .command(
@@ -65,6 +251,69 @@ const argv = yargs
         type: 'boolean',
         default: false,
       },
+      remap: {
+        description: 'JSON file mapping old object ids to new ones',
+        type: 'string',
+      },
+      values: {
+        description: 'JSON file of values for {{ name }} placeholders',
+        type: 'string',
+      },
+      secrets: {
+        description: 'JSON file of values for ${NAME} secret placeholders',
+        type: 'string',
+      },
+      types: {
+        alias: 't',
+        description: 'Only include objects of these types',
+        type: 'string',
+        array: true,
+      },
+      'fail-on-error': {
+        description: 'Exit non-zero when any object fails to import',
+        type: 'boolean',
+        default: true,
+      },
+      'fail-on-warning': {
+        description: 'Exit non-zero when the import returns warnings',
+        type: 'boolean',
+        default: false,
+      },
+      'dry-run': {
+        description: 'Only print what would be created or overwritten',
+        type: 'boolean',
+        default: false,
+      },
+      lock: {
+        description: 'Lockfile of object hashes, use --no-lock to skip it',
+        type: 'string',
+        default: 'kibob.lock',
+      },
+      force: {
+        description: 'Overwrite objects changed in Kibana since the lockfile',
+        type: 'boolean',
+        default: false,
+      },
+      all: {
+        description: 'Import objects that have not changed since the lockfile',
+        type: 'boolean',
+        default: false,
+      },
+      'short-urls': {
+        description: 'File of short URLs to recreate before importing',
+        type: 'string',
+      },
+      'prune-remote': {
+        description: 'Delete objects in the lockfile that left the file',
+        type: 'boolean',
+        default: false,
+      },
+      yes: {
+        alias: 'y',
+        description: 'Prune without asking for confirmation',
+        type: 'boolean',
+        default: false,
+      },
       url: {
         alias: 'u',
         description: 'The connection URL for the Kibana server',
@@ -72,240 +321,5628 @@ const argv = yargs
         default: 'http://localhost:5601',
       },
     },
-    (argv) => {
+    async (argv) => {
       setLogger(argv);
-      importObjects(argv);
+      if (argv.dryRun) report(argv, await planImport(argv), printPlan);
+      else report(argv, await importLocked(argv), printImport);
     }
   )
   .command(
-    'bundle',
-    'Bundles multiple .json files into one .ndjson file',
+    'clone',
+    'Copy saved objects from one named remote to another',
+    {
+      from: {
+        description: 'Named remote to copy objects from',
+        type: 'string',
+        demandOption: true,
+      },
+      to: {
+        description: 'Named remote to copy objects to',
+        type: 'string',
+        demandOption: true,
+      },
+      'map-space': {
+        description: 'Copy from one space into another, as source:target',
+        type: 'string',
+      },
+      search: {
+        alias: 's',
+        description: 'Search term to find objects by',
+      },
+      types: {
+        alias: 't',
+        description: 'Array of types to copy',
+        type: 'string',
+        array: true,
+        default: defaultTypes,
+      },
+      overwrite: {
+        alias: 'o',
+        description: 'forces overwrite of existing objects',
+        type: 'boolean',
+        default: false,
+      },
+      'fail-on-error': {
+        description: 'Exit non-zero when any object fails to import',
+        type: 'boolean',
+        default: true,
+      },
+      'fail-on-warning': {
+        description: 'Exit non-zero when the import returns warnings',
+        type: 'boolean',
+        default: false,
+      },
+    },
+    async (argv) => {
+      setLogger(argv);
+      report(argv, await cloneObjects(argv), printImport);
+    }
+  )
+  .command(
+    'snapshot',
+    'Save every object in Kibana to a timestamped file for rollback',
     {
       dir: {
         alias: 'd',
-        description: 'Target input directory',
+        description: 'Directory to keep snapshots in',
         type: 'string',
+        default: 'snapshots',
       },
-      file: {
-        alias: 'f',
-        description: 'Output filename',
+      url: {
+        alias: 'u',
+        description: 'The connection URL for the Kibana server',
         type: 'string',
-        default: 'saved_objects.ndjson',
+        default: 'http://localhost:5601',
+      },
+      types: {
+        alias: 't',
+        description: 'Array of types to snapshot',
+        type: 'string',
+        array: true,
+        default: defaultTypes,
       },
     },
-    (argv) => {
+    async (argv) => {
       setLogger(argv);
-      bundleObjects(argv);
+      report(argv, await snapshotObjects(argv));
     }
   )
   .command(
-    'unbundle',
-    'Unbundle single .ndjson file into multiple .json files',
+    'restore-backup [backup]',
+    'Restore the files a pull overwrote, from the latest backup by default',
+    {},
+    async (argv) => {
+      setLogger(argv);
+      report(argv, await restoreBackup(argv));
+    }
+  )
+  .command(
+    'rollback [snapshot]',
+    'Restore Kibana to a snapshot, the latest one if none is given',
     {
       dir: {
         alias: 'd',
-        description: 'Target output directory',
+        description: 'Directory snapshots are kept in',
+        type: 'string',
+        default: 'snapshots',
+      },
+      url: {
+        alias: 'u',
+        description: 'The connection URL for the Kibana server',
+        type: 'string',
+        default: 'http://localhost:5601',
+      },
+      prune: {
+        description: 'Delete objects of the snapshot types created since',
+        type: 'boolean',
+        default: false,
+      },
+      yes: {
+        alias: 'y',
+        description: 'Prune without asking for confirmation',
+        type: 'boolean',
+        default: false,
+      },
+    },
+    async (argv) => {
+      setLogger(argv);
+      report(argv, await rollbackObjects(argv), printImport);
+    }
+  )
+  .command(
+    'watch',
+    'Import .json files into Kibana as they change',
+    {
+      dir: {
+        alias: 'd',
+        description: 'Directory of unbundled .json files to watch',
         type: 'string',
         default: 'saved_objects',
       },
-      file: {
-        alias: 'f',
-        description: 'Input filename',
+      url: {
+        alias: 'u',
+        description: 'The connection URL for the Kibana server',
         type: 'string',
-        default: 'saved_objects.ndjson',
+        default: 'http://localhost:5601',
+      },
+      debounce: {
+        description: 'Milliseconds to wait for more changes before importing',
+        type: 'number',
+        default: 500,
+      },
+      secrets: {
+        description: 'JSON file of values for ${NAME} secret placeholders',
+        type: 'string',
+      },
+      poll: {
+        description: 'Also write changes made in Kibana every N seconds',
+        type: 'number',
+      },
+      search: {
+        alias: 's',
+        description: 'Search term to find objects by when polling',
+      },
+      types: {
+        alias: 't',
+        description: 'Array of types to find when polling',
+        type: 'string',
+        array: true,
+        default: defaultTypes,
+      },
+      filename: {
+        description: 'File name template using {title} {type} {id} {id_short}',
+        type: 'string',
+        default: '{title}.{type}.json',
+      },
+      expand: {
+        description: 'Expand JSON encoded attributes like visState',
+        type: 'boolean',
+        default: true,
+      },
+      normalize: {
+        description: 'Sort object keys and references for stable diffs',
+        type: 'boolean',
+        default: true,
+      },
+      lock: {
+        description: 'Lockfile of object hashes, use --no-lock to skip it',
+        type: 'string',
+        default: 'kibob.lock',
+      },
+      force: {
+        description: 'Overwrite objects changed in Kibana since the lockfile',
+        type: 'boolean',
+        default: false,
       },
     },
     (argv) => {
       setLogger(argv);
-      unbundleObjects(argv);
+      watchDirectory(argv);
     }
   )
-  .option('test', {
-    description: 'Test mode, only print to console',
-    type: 'boolean',
-  })
-  .option('debug', {
-    description: 'Log in debug mode',
-    type: 'boolean',
-  })
-  .option('verbose', {
-    alias: 'v',
-    description: 'Log in verbose mode',
-    type: 'boolean',
-  })
-  .help()
-  .alias('help', 'h').argv;
-
-// adjust logger level if command-line arguments were given
+  .command(
+    'graph',
+    'Print the reference graph of a directory as DOT or Mermaid',
+    {
+      dir: {
+        alias: 'd',
+        description: 'Directory of unbundled .json files',
+        type: 'string',
+        default: 'saved_objects',
+      },
+      format: {
+        description: 'Graph language to print',
+        choices: ['dot', 'mermaid'],
+        default: 'dot',
+      },
+      root: {
+        description: 'Only objects connected to this type/id',
+        type: 'string',
+      },
+    },
+    async (argv) => {
+      setLogger(argv);
+      report(argv, await referenceGraph(argv), (result) => {
+        const print = argv.format === 'mermaid' ? graph.toMermaid : graph.toDot;
+        process.stdout.write(print(result));
+      });
+    }
+  )
+  .command(
+    'list',
+    'List the saved objects in a directory of .json files',
+    {
+      dir: {
+        alias: 'd',
+        description: 'Directory of unbundled .json files',
+        type: 'string',
+        default: 'saved_objects',
+      },
+      types: {
+        alias: 't',
+        description: 'Only list objects of these types',
+        type: 'string',
+        array: true,
+      },
+    },
+    async (argv) => {
+      setLogger(argv);
+      report(argv, await listObjects(argv), printList);
+    }
+  )
+  .command('config <command>', 'Show the effective kibob settings', (yargs) =>
+    yargs
+      .command(
+        'show-transforms [type]',
+        'Show the transforms applied to each type from kibob.json',
+        {},
+        (argv) => {
+          setLogger(argv);
+          report(argv, showTransforms(argv), printTransforms);
+        }
+      )
+      .command(
+        'show-defaults',
+        'Show the option defaults set in config files and the environment',
+        {},
+        (argv) => {
+          setLogger(argv);
+          report(argv, showDefaults(), (defaults) =>
+            printTable(['option', 'value', 'source'], defaults)
+          );
+        }
+      )
+      .demandCommand()
+  )
+  .command('migrate <command>', 'Update files for this kibob', (yargs) =>
+    yargs
+      .command(
+        'env [files..]',
+        'Rename deprecated variables like KIBANA_SPACE in .env files',
+        {
+          files: { default: ['.env'] },
+        },
+        async (argv) => {
+          setLogger(argv);
+          report(argv, await migrateEnv(argv));
+        }
+      )
+      .demandCommand()
+  )
+  .command('auth <command>', 'Manage credentials in the OS keyring', (yargs) =>
+    yargs
+      .command(
+        'login <remote>',
+        'Check credentials for a remote and save them in the OS keyring',
+        {},
+        async (argv) => {
+          setLogger(argv);
+          report(argv, await authLogin(argv));
+        }
+      )
+      .command(
+        'logout <remote>',
+        'Remove the credentials saved for a remote',
+        {},
+        (argv) => {
+          setLogger(argv);
+          report(argv, authLogout(argv));
+        }
+      )
+      .demandCommand()
+  )
+  .command('dev <command>', 'Try objects out in a space of your own', (yargs) =>
+    yargs
+      .command(
+        'push',
+        'Import the objects into your dev-<user> space with suffixed ids',
+        {
+          dir: {
+            alias: 'd',
+            description: 'Directory of unbundled .json files to push',
+            type: 'string',
+            default: 'saved_objects',
+          },
+          url: {
+            alias: 'u',
+            description: 'The connection URL for the Kibana server',
+            type: 'string',
+            default: 'http://localhost:5601',
+          },
+          user: {
+            description: 'Name for the dev space, the OS user by default',
+            type: 'string',
+          },
+          types: {
+            alias: 't',
+            description: 'Only include objects of these types',
+            type: 'string',
+            array: true,
+          },
+          values: {
+            description: 'JSON file of values for {{ name }} placeholders',
+            type: 'string',
+          },
+          secrets: {
+            description: 'JSON file of values for ${NAME} secret placeholders',
+            type: 'string',
+          },
+        },
+        async (argv) => {
+          setLogger(argv);
+          report(argv, await devPush(argv), printDevPush);
+        }
+      )
+      .command(
+        'clean',
+        'Delete your dev-<user> space, or only the objects pushed to it',
+        {
+          url: {
+            alias: 'u',
+            description: 'The connection URL for the Kibana server',
+            type: 'string',
+            default: 'http://localhost:5601',
+          },
+          user: {
+            description: 'Name for the dev space, the OS user by default',
+            type: 'string',
+          },
+          'objects-only': {
+            description: 'Keep the space, deleting only the objects pushed',
+            type: 'boolean',
+            default: false,
+          },
+          types: {
+            alias: 't',
+            description: 'Types of objects to delete with --objects-only',
+            type: 'string',
+            array: true,
+            default: defaultTypes,
+          },
+          yes: {
+            alias: 'y',
+            description: 'Delete without asking for confirmation',
+            type: 'boolean',
+            default: false,
+          },
+        },
+        async (argv) => {
+          setLogger(argv);
+          report(argv, await devClean(argv));
+        }
+      )
+      .demandCommand()
+  )
+  .command(
+    'doctor',
+    'Check the Kibana connection and the local setup for problems',
+    {
+      dir: {
+        alias: 'd',
+        description: 'Directory of unbundled .json files',
+        type: 'string',
+        default: 'saved_objects',
+      },
+      url: {
+        alias: 'u',
+        description: 'The connection URL for the Kibana server',
+        type: 'string',
+        default: 'http://localhost:5601',
+      },
+    },
+    async (argv) => {
+      setLogger(argv);
+      report(argv, await doctor(argv), printChecks);
+    }
+  )
+  .command(
+    'diff',
+    'Summarize changes to .json files since the last git commit',
+    {
+      dir: {
+        alias: 'd',
+        description: 'Directory of unbundled .json files',
+        type: 'string',
+        default: 'saved_objects',
+      },
+      from: {
+        description: 'Git ref to compare from, like a release tag',
+        type: 'string',
+        default: 'HEAD',
+      },
+      to: {
+        description: 'Git ref to compare to, default: the files on disk',
+        type: 'string',
+      },
+      exact: {
+        description: 'Count moving and resizing dashboard panels as changes',
+        type: 'boolean',
+        default: false,
+      },
+    },
+    async (argv) => {
+      setLogger(argv);
+      report(argv, await diffDirectory(argv), printDiff);
+    }
+  )
+  .command(
+    'validate',
+    'Check .json files for invalid JSON, duplicates and broken references',
+    {
+      dir: {
+        alias: 'd',
+        description: 'Directory of unbundled .json files',
+        type: 'string',
+        default: 'saved_objects',
+      },
+      prune: {
+        description: 'Delete files orphaned by objects that moved to new files',
+        type: 'boolean',
+        default: false,
+      },
+    },
+    async (argv) => {
+      setLogger(argv);
+      report(argv, await validateDirectory(argv));
+    }
+  )
+  .command(
+    'rm <type> <id>',
+    'Remove a saved object file, and optionally the object in Kibana',
+    {
+      dir: {
+        alias: 'd',
+        description: 'Directory of unbundled .json files',
+        type: 'string',
+        default: 'saved_objects',
+      },
+      kibana: {
+        description: 'Also delete the object from Kibana',
+        type: 'boolean',
+        default: false,
+      },
+      url: {
+        alias: 'u',
+        description: 'The connection URL for the Kibana server',
+        type: 'string',
+        default: 'http://localhost:5601',
+      },
+    },
+    async (argv) => {
+      setLogger(argv);
+      const result = { removed: await removeObject(argv) };
+      if (argv.kibana) result.deleted = await deleteObject(argv);
+      report(argv, result);
+    }
+  )
+  .command(
+    'rename <type> <id>',
+    'Change an object\'s title or id, its file name, and references to it',
+    {
+      dir: {
+        alias: 'd',
+        description: 'Directory of unbundled .json files',
+        type: 'string',
+        default: 'saved_objects',
+      },
+      title: {
+        description: 'New title for the object',
+        type: 'string',
+      },
+      'new-id': {
+        description: 'New id for the object, references to it are updated',
+        type: 'string',
+      },
+      filename: {
+        description: 'File name template using {title} {type} {id} {id_short}',
+        type: 'string',
+        default: '{title}.{type}.json',
+      },
+      expand: {
+        description: 'Expand JSON encoded attributes like visState',
+        type: 'boolean',
+        default: true,
+      },
+      normalize: {
+        description: 'Sort object keys and references for stable diffs',
+        type: 'boolean',
+        default: true,
+      },
+    },
+    async (argv) => {
+      setLogger(argv);
+      report(argv, await renameObject(argv));
+    }
+  )
+  .command(
+    'bundle',
+    'Bundles multiple .json files into one .ndjson file',
+    {
+      dir: {
+        alias: 'd',
+        description: 'Target input directory',
+        type: 'string',
+      },
+      file: {
+        alias: 'f',
+        description: 'Output filename',
+        type: 'string',
+        default: 'saved_objects.ndjson',
+      },
+      remap: {
+        description: 'JSON file mapping old object ids to new ones',
+        type: 'string',
+      },
+      values: {
+        description: 'JSON file of values for {{ name }} placeholders',
+        type: 'string',
+      },
+      secrets: {
+        description: 'JSON file of values for ${NAME} secret placeholders',
+        type: 'string',
+      },
+      types: {
+        alias: 't',
+        description: 'Only include objects of these types',
+        type: 'string',
+        array: true,
+      },
+      changelog: {
+        description: 'Add a section on the changes since --since to this file',
+        type: 'string',
+      },
+      since: {
+        description: 'Git ref of the previous release, default: latest tag',
+        type: 'string',
+      },
+      release: {
+        description: 'Title of the changelog section, default: --tag or date',
+        type: 'string',
+      },
+      tag: {
+        description: 'Git tag to create for this release of the bundle',
+        type: 'string',
+      },
+    },
+    async (argv) => {
+      setLogger(argv);
+      report(argv, await bundleObjects(argv));
+    }
+  )
+  .command(
+    'unbundle',
+    'Unbundle single .ndjson file into multiple .json files',
+    {
+      dir: {
+        alias: 'd',
+        description: 'Target output directory',
+        type: 'string',
+        default: 'saved_objects',
+      },
+      file: {
+        alias: 'f',
+        description: 'Input filename',
+        type: 'string',
+        default: 'saved_objects.ndjson',
+      },
+      filename: {
+        description: 'File name template using {title} {type} {id} {id_short}',
+        type: 'string',
+        default: '{title}.{type}.json',
+      },
+      expand: {
+        description: 'Expand JSON encoded attributes like visState',
+        type: 'boolean',
+        default: true,
+      },
+      normalize: {
+        description: 'Sort object keys and references for stable diffs',
+        type: 'boolean',
+        default: true,
+      },
+      force: {
+        description: 'Overwrite files that have uncommitted changes',
+        type: 'boolean',
+        default: false,
+      },
+      values: {
+        description: 'JSON file of values to turn back into {{ name }}',
+        type: 'string',
+      },
+      template: {
+        description: 'Turn --values back into {{ name }} placeholders',
+        type: 'boolean',
+        default: false,
+      },
+      commit: {
+        description: 'Commit the changed files to git with a summary',
+        type: 'boolean',
+        default: false,
+      },
+    },
+    async (argv) => {
+      setLogger(argv);
+      const result = await unbundleObjects(argv);
+      if (result && argv.commit) await commitPull(argv, argv.file);
+      report(argv, result);
+    }
+  )
+  .command(
+    'add',
+    'Add the object behind a Kibana share link to a directory of .json files',
+    {
+      'from-url': {
+        description: 'Kibana share link or short URL of the object to add',
+        type: 'string',
+        demandOption: true,
+      },
+      url: {
+        alias: 'u',
+        description: 'The connection URL for Kibana, default: the link host',
+        type: 'string',
+      },
+      dir: {
+        alias: 'd',
+        description: 'Target output directory',
+        type: 'string',
+        default: 'saved_objects',
+      },
+      references: {
+        description: 'Also add everything the object references',
+        type: 'boolean',
+        default: true,
+      },
+      filename: {
+        description: 'File name template using {title} {type} {id} {id_short}',
+        type: 'string',
+        default: '{title}.{type}.json',
+      },
+      drop: {
+        description: 'Fields to strip from added objects, * matches any',
+        type: 'string',
+        array: true,
+        default: volatileFields,
+      },
+      expand: {
+        description: 'Expand JSON encoded attributes like visState',
+        type: 'boolean',
+        default: true,
+      },
+      normalize: {
+        description: 'Sort object keys and references for stable diffs',
+        type: 'boolean',
+        default: true,
+      },
+      force: {
+        description: 'Overwrite files that have uncommitted changes',
+        type: 'boolean',
+        default: false,
+      },
+      values: {
+        description: 'JSON file of values to turn back into {{ name }}',
+        type: 'string',
+      },
+      template: {
+        description: 'Turn --values back into {{ name }} placeholders',
+        type: 'boolean',
+        default: false,
+      },
+      commit: {
+        description: 'Commit the changed files to git with a summary',
+        type: 'boolean',
+        default: false,
+      },
+    },
+    async (argv) => {
+      setLogger(argv);
+      const result = await addFromUrl(argv);
+      if (result && argv.commit) await commitPull(argv, lockTarget(argv));
+      report(argv, result);
+    }
+  )
+  .command(
+    'init',
+    'Start a project from the objects in a Kibana space',
+    {
+      'from-kibana': {
+        description: 'Start from the objects in a live Kibana space',
+        type: 'boolean',
+        default: true,
+      },
+      url: {
+        alias: 'u',
+        description: 'The connection URL for the Kibana server',
+        type: 'string',
+        default: 'http://localhost:5601',
+      },
+      dir: {
+        alias: 'd',
+        description: 'Directory to pull the objects into',
+        type: 'string',
+        default: 'saved_objects',
+      },
+      types: {
+        alias: 't',
+        description: 'Types of objects to start from, referenced ones come too',
+        type: 'string',
+        array: true,
+        default: ['dashboard'],
+      },
+      search: {
+        alias: 's',
+        description: 'Search term to find objects by',
+      },
+      filename: {
+        description: 'File name template using {title} {type} {id} {id_short}',
+        type: 'string',
+        default: '{title}.{type}.json',
+      },
+      drop: {
+        description: 'Fields to strip from objects, * matches any',
+        type: 'string',
+        array: true,
+        default: volatileFields,
+      },
+      expand: {
+        description: 'Expand JSON encoded attributes like visState',
+        type: 'boolean',
+        default: true,
+      },
+      normalize: {
+        description: 'Sort object keys and references for stable diffs',
+        type: 'boolean',
+        default: true,
+      },
+      lock: {
+        description: 'Lockfile of object hashes, use --no-lock to skip it',
+        type: 'string',
+        default: 'kibob.lock',
+      },
+    },
+    async (argv) => {
+      setLogger(argv);
+      report(argv, await initProject(argv));
+    }
+  )
+  .command(
+    'split <file>',
+    'Split any .ndjson export into a directory of .json files per type',
+    {
+      dir: {
+        alias: 'd',
+        description: 'Target output directory, default: the file name',
+        type: 'string',
+      },
+      filename: {
+        description: 'File name template using {title} {type} {id} {id_short}',
+        type: 'string',
+        default: '{title}.json',
+      },
+      expand: {
+        description: 'Expand JSON encoded attributes like visState',
+        type: 'boolean',
+        default: true,
+      },
+      normalize: {
+        description: 'Sort object keys and references for stable diffs',
+        type: 'boolean',
+        default: true,
+      },
+    },
+    async (argv) => {
+      setLogger(argv);
+      report(argv, await splitObjects(argv));
+    }
+  )
+  .command(
+    'join <dir>',
+    'Join a directory split by type back into a single .ndjson file',
+    {
+      file: {
+        alias: 'f',
+        description: 'Output filename, default: the directory name',
+        type: 'string',
+      },
+    },
+    async (argv) => {
+      setLogger(argv);
+      report(argv, await joinObjects(argv));
+    }
+  )
+  .command(
+    'dedupe <dirs..>',
+    'Keep objects that are the same in several directories in one place',
+    {
+      shared: {
+        description: 'Directory to keep the shared objects in',
+        type: 'string',
+        default: '_shared',
+      },
+      filename: {
+        description: 'File name template using {title} {type} {id} {id_short}',
+        type: 'string',
+        default: '{title}.{type}.json',
+      },
+      expand: {
+        description: 'Expand JSON encoded attributes like visState',
+        type: 'boolean',
+        default: true,
+      },
+      normalize: {
+        description: 'Sort object keys and references for stable diffs',
+        type: 'boolean',
+        default: true,
+      },
+    },
+    async (argv) => {
+      setLogger(argv);
+      report(argv, await dedupeObjects(argv), (shared) =>
+        printTable(['file', 'copies'], shared)
+      );
+    }
+  )
+  .command(
+    'cases-export',
+    'Export case settings, templates and custom fields from Kibana',
+    {
+      file: {
+        alias: 'f',
+        description: 'Name of file to export to',
+        type: 'string',
+        default: 'cases.json',
+      },
+      url: {
+        alias: 'u',
+        description: 'The connection URL for the Kibana server',
+        type: 'string',
+        default: 'http://localhost:5601',
+      },
+    },
+    async (argv) => {
+      setLogger(argv);
+      report(argv, await exportCases(argv));
+    }
+  )
+  .command(
+    'cases-import',
+    'Create or update case settings in Kibana from a file',
+    {
+      file: {
+        alias: 'f',
+        description: 'File of case settings to import',
+        type: 'string',
+        default: 'cases.json',
+      },
+      url: {
+        alias: 'u',
+        description: 'The connection URL for the Kibana server',
+        type: 'string',
+        default: 'http://localhost:5601',
+      },
+    },
+    async (argv) => {
+      setLogger(argv);
+      report(argv, await importCases(argv), (results) =>
+        printTable(['owner', 'action'], results)
+      );
+    }
+  )
+  .command(
+    'roles-export',
+    'Export Kibana roles, skipping the built in reserved roles',
+    {
+      file: {
+        alias: 'f',
+        description: 'Name of file to export to',
+        type: 'string',
+        default: 'roles.json',
+      },
+      url: {
+        alias: 'u',
+        description: 'The connection URL for the Kibana server',
+        type: 'string',
+        default: 'http://localhost:5601',
+      },
+    },
+    async (argv) => {
+      setLogger(argv);
+      report(argv, await exportRoles(argv));
+    }
+  )
+  .command(
+    'roles-import',
+    'Create or replace Kibana roles from a file',
+    {
+      file: {
+        alias: 'f',
+        description: 'File of roles to import',
+        type: 'string',
+        default: 'roles.json',
+      },
+      url: {
+        alias: 'u',
+        description: 'The connection URL for the Kibana server',
+        type: 'string',
+        default: 'http://localhost:5601',
+      },
+    },
+    async (argv) => {
+      setLogger(argv);
+      report(argv, await importRoles(argv), (results) =>
+        printTable(['name', 'action'], results)
+      );
+    }
+  )
+  .command(
+    'slos-export',
+    'Export SLO definitions from Kibana',
+    {
+      file: {
+        alias: 'f',
+        description: 'Name of file to export to',
+        type: 'string',
+        default: 'slos.json',
+      },
+      url: {
+        alias: 'u',
+        description: 'The connection URL for the Kibana server',
+        type: 'string',
+        default: 'http://localhost:5601',
+      },
+    },
+    async (argv) => {
+      setLogger(argv);
+      report(argv, await exportSlos(argv));
+    }
+  )
+  .command(
+    'slos-import',
+    'Create or update SLO definitions in Kibana from a file',
+    {
+      file: {
+        alias: 'f',
+        description: 'File of SLO definitions to import',
+        type: 'string',
+        default: 'slos.json',
+      },
+      url: {
+        alias: 'u',
+        description: 'The connection URL for the Kibana server',
+        type: 'string',
+        default: 'http://localhost:5601',
+      },
+    },
+    async (argv) => {
+      setLogger(argv);
+      report(argv, await importSlos(argv), (results) =>
+        printTable(['id', 'name', 'action'], results)
+      );
+    }
+  )
+  .command(
+    'connectors-export',
+    'Export alerting connectors from Kibana, secrets as placeholders',
+    {
+      file: {
+        alias: 'f',
+        description: 'Name of file to export to',
+        type: 'string',
+        default: 'connectors.json',
+      },
+      url: {
+        alias: 'u',
+        description: 'The connection URL for the Kibana server',
+        type: 'string',
+        default: 'http://localhost:5601',
+      },
+    },
+    async (argv) => {
+      setLogger(argv);
+      report(argv, await exportConnectors(argv));
+    }
+  )
+  .command(
+    'connectors-import',
+    'Create or update alerting connectors, secrets from the environment',
+    {
+      file: {
+        alias: 'f',
+        description: 'File of connectors to import',
+        type: 'string',
+        default: 'connectors.json',
+      },
+      url: {
+        alias: 'u',
+        description: 'The connection URL for the Kibana server',
+        type: 'string',
+        default: 'http://localhost:5601',
+      },
+    },
+    async (argv) => {
+      setLogger(argv);
+      report(argv, await importConnectors(argv), (results) =>
+        printTable(['id', 'name', 'action'], results)
+      );
+    }
+  )
+  .command(
+    'rules-export',
+    'Export alerting rules from Kibana',
+    {
+      file: {
+        alias: 'f',
+        description: 'Name of file to export to',
+        type: 'string',
+        default: 'rules.json',
+      },
+      url: {
+        alias: 'u',
+        description: 'The connection URL for the Kibana server',
+        type: 'string',
+        default: 'http://localhost:5601',
+      },
+    },
+    async (argv) => {
+      setLogger(argv);
+      report(argv, await exportRules(argv));
+    }
+  )
+  .command(
+    'rules-import',
+    'Create or update alerting rules in Kibana from a file',
+    {
+      file: {
+        alias: 'f',
+        description: 'File of alerting rules to import',
+        type: 'string',
+        default: 'rules.json',
+      },
+      url: {
+        alias: 'u',
+        description: 'The connection URL for the Kibana server',
+        type: 'string',
+        default: 'http://localhost:5601',
+      },
+    },
+    async (argv) => {
+      setLogger(argv);
+      report(argv, await importRules(argv), (results) =>
+        printTable(['id', 'name', 'action'], results)
+      );
+    }
+  )
+  .command(
+    'data-views-export',
+    'Export data views from Kibana with the data views API',
+    {
+      file: {
+        alias: 'f',
+        description: 'Name of file to export to',
+        type: 'string',
+        default: 'data-views.json',
+      },
+      url: {
+        alias: 'u',
+        description: 'The connection URL for the Kibana server',
+        type: 'string',
+        default: 'http://localhost:5601',
+      },
+    },
+    async (argv) => {
+      setLogger(argv);
+      report(argv, await exportDataViews(argv));
+    }
+  )
+  .command(
+    'data-views-import',
+    'Create or update data views in Kibana from a file',
+    {
+      file: {
+        alias: 'f',
+        description: 'File of data views to import',
+        type: 'string',
+        default: 'data-views.json',
+      },
+      url: {
+        alias: 'u',
+        description: 'The connection URL for the Kibana server',
+        type: 'string',
+        default: 'http://localhost:5601',
+      },
+    },
+    async (argv) => {
+      setLogger(argv);
+      report(argv, await importDataViews(argv), (results) =>
+        printTable(['id', 'name', 'action'], results)
+      );
+    }
+  )
+  .command(
+    'detection-rules-export',
+    'Export custom security detection rules and their exception lists',
+    {
+      file: {
+        alias: 'f',
+        description: 'Name of file to export to',
+        type: 'string',
+        default: 'detection-rules.json',
+      },
+      url: {
+        alias: 'u',
+        description: 'The connection URL for the Kibana server',
+        type: 'string',
+        default: 'http://localhost:5601',
+      },
+    },
+    async (argv) => {
+      setLogger(argv);
+      report(argv, await exportDetectionRules(argv));
+    }
+  )
+  .command(
+    'detection-rules-import',
+    'Create or overwrite security detection rules in Kibana from a file',
+    {
+      file: {
+        alias: 'f',
+        description: 'File of detection rules to import',
+        type: 'string',
+        default: 'detection-rules.json',
+      },
+      url: {
+        alias: 'u',
+        description: 'The connection URL for the Kibana server',
+        type: 'string',
+        default: 'http://localhost:5601',
+      },
+    },
+    async (argv) => {
+      setLogger(argv);
+      report(argv, await importDetectionRules(argv), (results) =>
+        printTable(['rule_id', 'name', 'action'], results)
+      );
+    }
+  )
+  .command(
+    'settings-export',
+    'Export the advanced settings of a space from Kibana',
+    {
+      file: {
+        alias: 'f',
+        description: 'Name of file to export to',
+        type: 'string',
+        default: 'settings.json',
+      },
+      url: {
+        alias: 'u',
+        description: 'The connection URL for the Kibana server',
+        type: 'string',
+        default: 'http://localhost:5601',
+      },
+      keys: {
+        alias: 'k',
+        description: 'Settings to export, default: "settings" in kibob.json',
+        type: 'string',
+        array: true,
+      },
+    },
+    async (argv) => {
+      setLogger(argv);
+      report(argv, await exportSettings(argv));
+    }
+  )
+  .command(
+    'settings-import',
+    'Apply advanced settings from a file to a space in Kibana',
+    {
+      file: {
+        alias: 'f',
+        description: 'File of advanced settings to import',
+        type: 'string',
+        default: 'settings.json',
+      },
+      url: {
+        alias: 'u',
+        description: 'The connection URL for the Kibana server',
+        type: 'string',
+        default: 'http://localhost:5601',
+      },
+      keys: {
+        alias: 'k',
+        description: 'Settings to import, default: "settings" in kibob.json',
+        type: 'string',
+        array: true,
+      },
+    },
+    async (argv) => {
+      setLogger(argv);
+      report(argv, await importSettings(argv));
+    }
+  )
+  .command(
+    'fleet-export',
+    'Export Fleet agent policies and integration policies from Kibana',
+    {
+      file: {
+        alias: 'f',
+        description: 'Name of file to export to',
+        type: 'string',
+        default: 'fleet.json',
+      },
+      url: {
+        alias: 'u',
+        description: 'The connection URL for the Kibana server',
+        type: 'string',
+        default: 'http://localhost:5601',
+      },
+    },
+    async (argv) => {
+      setLogger(argv);
+      report(argv, await exportFleet(argv));
+    }
+  )
+  .command(
+    'fleet-import',
+    'Create or update Fleet agent and integration policies from a file',
+    {
+      file: {
+        alias: 'f',
+        description: 'File of Fleet policies to import',
+        type: 'string',
+        default: 'fleet.json',
+      },
+      values: {
+        description: 'JSON file of values for {{ name }} placeholders',
+        type: 'string',
+      },
+      url: {
+        alias: 'u',
+        description: 'The connection URL for the Kibana server',
+        type: 'string',
+        default: 'http://localhost:5601',
+      },
+    },
+    async (argv) => {
+      setLogger(argv);
+      report(argv, await importFleet(argv), (results) =>
+        printTable(['kind', 'id', 'name', 'action'], results)
+      );
+    }
+  )
+  .option('remote', {
+    description: 'Named remote from remotes.json to connect to',
+    type: 'string',
+  })
+  .option('workspace', {
+    description: 'Run in these workspaces from workspaces.json, or all',
+    type: 'string',
+  })
+  .option('all-remotes', {
+    description: 'Run against every remote in remotes.json',
+    type: 'boolean',
+  })
+  .option('concurrency', {
+    description: 'Requests to make at once, or remotes with --all-remotes',
+    type: 'number',
+    default: 4,
+  })
+  .option('space', {
+    description: 'Kibana space to work in, default space if unset',
+    type: 'string',
+  })
+  .middleware(resolveSpace)
+  .middleware(resolveRemote)
+  .middleware(lockState)
+  .option('retries', {
+    description: 'Retries for throttled, unavailable or failed requests',
+    type: 'number',
+    default: 3,
+  })
+  .option('retry-delay', {
+    description: 'Initial retry backoff in milliseconds, doubled each retry',
+    type: 'number',
+    default: 500,
+  })
+  .option('rate-limit', {
+    description: 'Maximum Kibana API requests per second',
+    type: 'number',
+  })
+  .option('ca', {
+    description: 'PEM file of certificate authorities to trust',
+    type: 'string',
+    default: process.env.KIBANA_CA_CERT,
+  })
+  .option('cert', {
+    description: 'PEM client certificate for mutual TLS',
+    type: 'string',
+    default: process.env.KIBANA_CLIENT_CERT,
+  })
+  .option('key', {
+    description: 'PEM private key for the client certificate',
+    type: 'string',
+    default: process.env.KIBANA_CLIENT_KEY,
+  })
+  .option('insecure', {
+    description: 'Skip TLS certificate verification',
+    type: 'boolean',
+    default: process.env.KIBANA_INSECURE === 'true',
+  })
+  .option('proxy', {
+    description: 'Proxy URL for Kibana requests, may include credentials',
+    type: 'string',
+    default: process.env.KIBANA_PROXY,
+  })
+  .option('max-sockets', {
+    description: 'Maximum open connections to Kibana, reused between requests',
+    type: 'number',
+    default: 8,
+  })
+  .option('batch-size', {
+    description: 'Most objects to send in one export or import request',
+    type: 'number',
+    default: 1000,
+  })
+  .option('lock-timeout', {
+    description: 'Seconds to wait for another kibob in this checkout to finish',
+    type: 'number',
+    default: 60,
+  })
+  .option('lock-per-write', {
+    description: 'Take the lock for each write, as runs of --all-remotes do',
+    type: 'boolean',
+    hidden: true,
+  })
+  .option('output', {
+    description: 'Print the result as JSON on stdout, logs go to stderr',
+    choices: ['text', 'json'],
+    default: 'text',
+  })
+  .option('strict', {
+    description: 'Fail on warnings as well as errors, for CI',
+    type: 'boolean',
+    default: false,
+  })
+  .option('test', {
+    description: 'Test mode, only print to console',
+    type: 'boolean',
+  })
+  .option('debug', {
+    description: 'Log in debug mode',
+    type: 'boolean',
+  })
+  .option('verbose', {
+    alias: 'v',
+    description: 'Log in verbose mode',
+    type: 'boolean',
+  })
+  .completion('completion', 'Generate a shell completion script')
+  .fail((message, err, parser) => {
+    if (err) {
+      fail();
+      logger.error(err.message);
+      return;
+    }
+    parser.showHelp();
+    console.error(`\n${message}`);
+    process.exit(exitCodes.usage);
+  })
+  .help()
+  .alias('help', 'h');
+
+/* Whether kibob is used as a library, where failures are returned or thrown
+ * to the caller and process.exitCode is left alone.
+ */
+let libraryMode = false;
+
+// Set the exit code for a failure, unless an earlier failure already did
+function fail(code = exitCodes.failure) {
+  if (libraryMode) return;
+  if (!process.exitCode) process.exitCode = code;
+}
+
+/* Log the error that ended a command and fail it, or throw it back to the
+ * caller when kibob is used as a library.
+ */
+function commandError(err) {
+  if (libraryMode) throw err;
+  logger.error(err.message);
+  fail();
+}
+
+// Fail the command on any error logged, and with --strict on any warning
+const failOnLog = format((info, { strict }) => {
+  if (info.level === 'error' || (strict && info.level === 'warn')) fail();
+  // runWorkspaces reports the errors of every workspace at the end
+  if (info.level === 'error' && process.send) {
+    process.send({ error: String(info.message) });
+  }
+  return info;
+});
+
+// adjust logger level if command-line arguments were given
 function setLogger(argv) {
   logger.level = argv.debug ? 'debug' : argv.verbose ? 'verbose' : 'info';
+  logger.format = format.combine(failOnLog({ strict: argv.strict }), logFormat);
+  // keep stdout for the JSON result
+  if (argv.output === 'json') {
+    for (const transport of logger.transports) {
+      transport.stderrLevels = R.map(R.T, logger.levels);
+    }
+  }
+  for (const message of argv.deprecations || []) logger.warn(message);
+}
+
+// Print a command's result as JSON with --output json, or as text with print
+function report(argv, result, print = () => {}) {
+  if (result === undefined) return;
+  // a command run by runWorkspaces hands its result up for the summary
+  if (process.send) process.send({ result });
+  if (argv.output === 'json') console.log(JSON.stringify(result, null, 2));
+  else print(result);
+}
+
+/* Pick the space from, in order, --space, KIBOB_SPACE, the option defaults
+ * in the config files, and last the deprecated KIBANA_SPACE, which warns
+ * once the command sets up logging.
+ */
+function resolveSpace(argv) {
+  if (argv.space || !process.env.KIBANA_SPACE) return argv;
+  argv.space = process.env.KIBANA_SPACE;
+  argv.deprecations = [
+    'KIBANA_SPACE is deprecated, set KIBOB_SPACE instead ' +
+      'or run `kibob migrate env` to update .env files',
+  ];
+  return argv;
+}
+
+/* Rename deprecated variables in .env files, keeping everything else about
+ * each line. Returns the files changed.
+ */
+async function migrateEnv(argv) {
+  const renames = { KIBANA_SPACE: 'KIBOB_SPACE' };
+  const pattern = new RegExp(
+    `^(\\s*(?:export\\s+)?)(${Object.keys(renames).join('|')})(?==)`,
+    'gm'
+  );
+  const changed = [];
+  try {
+    for (const file of argv.files) {
+      const text = await fs.readFile(file, 'utf8');
+      const migrated = text.replace(
+        pattern,
+        (match, prefix, name) => prefix + renames[name]
+      );
+      if (migrated === text) continue;
+      await state.writeAtomic(file, migrated);
+      logger.info(`Updated ${file}`);
+      changed.push(file);
+    }
+  } catch (err) {
+    commandError(err);
+  }
+  return changed;
+}
+
+// Commands that only read files, which run alongside any other
+const readOnlyCommands = [
+  'audit',
+  'auth',
+  'config',
+  'diff',
+  'doctor',
+  'graph',
+  'list',
+  'status',
+  'validate',
+];
+
+// Commands that run until stopped, and take the lock for each change instead
+const lockPerChangeCommands = ['watch'];
+
+/* Commands that write what they pull from Kibana into the checkout, where
+ * runs for several remotes would overwrite each other's files
+ */
+const pullCommands = ['add', 'export', 'init', 'snapshot', 'watch'];
+const isPullCommand = (command) =>
+  pullCommands.includes(command) || /-export$/.test(command || '');
+
+/* Hold the lock on the .kibob state directory while a command that writes
+ * files runs, so two users or CI jobs in one checkout take turns.
+ */
+async function lockState(argv) {
+  const command = argv._[0];
+  if (!command || readOnlyCommands.includes(command)) return argv;
+  if (lockPerChangeCommands.includes(command)) return argv;
+  if (argv.lockPerWrite) return argv;
+  try {
+    await state.acquireLock(command, argv.lockTimeout);
+  } catch (err) {
+    logger.error(err.message);
+    process.exit(exitCodes.failure);
+  }
+  return argv;
+}
+
+// Replace the URL with a named remote's URL and credentials from remotes.json
+function resolveRemote(argv) {
+  if (!argv.remote) return argv;
+  const { url, apiKey, spaces, values } = remoteConfig(argv.remote);
+  argv.url = argv.u = url;
+  argv.apiKey = apiKey;
+  if (!argv.values && values) argv.values = values;
+  if (argv.space && spaces[argv.space]) argv.space = spaces[argv.space];
+  return argv;
+}
+
+// Look up a named remote's URL and credentials in remotes.json
+function remoteConfig(name) {
+  const remotes = JSON.parse(
+    require('fs').readFileSync('remotes.json', 'utf8')
+  );
+  const remote = remotes[name];
+  if (!remote) {
+    throw new KibobError(
+      'REMOTE_NOT_FOUND',
+      `Remote '${name}' is not defined in remotes.json`
+    );
+  }
+
+  // credentials from the environment win over those saved by auth login
+  const url = new URL(remote.url);
+  const env = (field) => remote[field] && process.env[remote[field]];
+  const fromEnv = env('apiKeyEnv') || env('usernameEnv') || url.username;
+  const saved = fromEnv ? {} : keyringCredentials(name);
+  const username = env('usernameEnv') || saved.username;
+  const password = env('passwordEnv') || saved.password;
+  if (remote.usernameEnv || username) url.username = username || '';
+  if (remote.passwordEnv || password) url.password = password || '';
+  return {
+    url: url.toString(),
+    apiKey: env('apiKeyEnv') || saved.apiKey,
+    spaces: remote.spaces || {},
+    values: remote.values,
+  };
+}
+
+const keyringService = 'kibob';
+
+/* Run an OS keyring command on the credentials of a remote: the Keychain on
+ * macOS and the Secret Service through secret-tool on Linux. Both read the
+ * secret to store from stdin, so it never shows on a command line in ps;
+ * security asks for it twice. Returns the command's output.
+ */
+function keyring(action, remote, secret) {
+  const item = ['-s', keyringService, '-a', remote];
+  const attributes = ['service', keyringService, 'remote', remote];
+  const label = `--label=kibob ${remote}`;
+  const commands = {
+    darwin: {
+      store: ['security', ['add-generic-password', '-U', ...item, '-w']],
+      lookup: ['security', ['find-generic-password', ...item, '-w']],
+      clear: ['security', ['delete-generic-password', ...item]],
+    },
+    linux: {
+      store: ['secret-tool', ['store', label, ...attributes]],
+      lookup: ['secret-tool', ['lookup', ...attributes]],
+      clear: ['secret-tool', ['clear', ...attributes]],
+    },
+  }[process.platform];
+  if (!commands) {
+    throw new KibobError(
+      'NO_KEYRING',
+      `No supported OS keyring on ${process.platform}`
+    );
+  }
+  const [file, args] = commands[action];
+  const input =
+    secret && process.platform === 'darwin' ? `${secret}\n${secret}\n` : secret;
+  const options = { input, encoding: 'utf8', stdio: 'pipe' };
+  if (action !== 'lookup') delete keyringCache[remote];
+  return execFileSync(file, args, options).trim();
+}
+
+// Credentials looked up in the keyring so far, by remote
+const keyringCache = {};
+
+// Credentials saved for a remote by auth login, empty if there are none
+function keyringCredentials(remote) {
+  if (!keyringCache[remote]) {
+    let credentials = {};
+    try {
+      credentials = JSON.parse(keyring('lookup', remote));
+    } catch (err) {
+      // nothing saved, or no keyring to look in
+    }
+    keyringCache[remote] = credentials;
+  }
+  return keyringCache[remote];
+}
+
+/* Prompt for an API key, or a username and password, check them against the
+ * remote's Kibana and save them in the OS keyring. Secrets are not echoed.
+ */
+async function authLogin(argv) {
+  const prompt = readline.createInterface({
+    input: process.stdin,
+    output: process.stderr,
+    terminal: true,
+  });
+  let muted = false;
+  prompt._writeToOutput = (text) => muted || process.stderr.write(text);
+  const question = (text) =>
+    new Promise((resolve) => prompt.question(text, resolve));
+  const secret = async (text) => {
+    process.stderr.write(text);
+    muted = true;
+    const answer = await question('');
+    muted = false;
+    process.stderr.write('\n');
+    return answer;
+  };
+
+  try {
+    const apiKey = await secret('API key (empty to use a username): ');
+    const credentials = apiKey
+      ? { apiKey }
+      : {
+          username: await question('Username: '),
+          password: await secret('Password: '),
+        };
+
+    const url = new URL(remoteConfig(argv.remote).url);
+    url.username = credentials.username || '';
+    url.password = credentials.password || '';
+    const check = { ...argv, url: url.toString(), apiKey };
+    const { status, statusText, body } = await getJson(check, '/api/status');
+    if (status !== 200) throw apiError(status, statusText, body);
+
+    keyring('store', argv.remote, JSON.stringify(credentials));
+    logger.info(`Saved credentials for ${argv.remote} in the OS keyring`);
+    return { remote: argv.remote, auth: apiKey ? 'apiKey' : 'basic' };
+  } catch (err) {
+    commandError(err);
+  } finally {
+    prompt.close();
+  }
+}
+
+function authLogout(argv) {
+  try {
+    keyring('clear', argv.remote);
+    logger.info(`Removed the credentials saved for ${argv.remote}`);
+    return { remote: argv.remote };
+  } catch (err) {
+    commandError(err);
+  }
+}
+
+const projectConfigFile = 'kibob.json';
+let projectConfig;
+let projectConfigDir;
+
+/* The project's kibob.json from the working directory, empty if there is
+ * none. Read again when a library caller changes directory.
+ */
+function readProjectConfig() {
+  if (!projectConfig || projectConfigDir !== process.cwd()) {
+    projectConfigDir = process.cwd();
+    try {
+      const text = require('fs').readFileSync(projectConfigFile, 'utf8');
+      projectConfig = JSON.parse(text);
+    } catch (err) {
+      if (err.code !== 'ENOENT') throw err;
+      projectConfig = {};
+    }
+  }
+  return projectConfig;
+}
+
+// The user's own config, for every project they work on
+function userConfigFile() {
+  const home = process.env.XDG_CONFIG_HOME || `${os.homedir()}/.config`;
+  return path.join(home, 'kibob', 'config.json');
+}
+
+function readUserConfig() {
+  try {
+    return JSON.parse(require('fs').readFileSync(userConfigFile(), 'utf8'));
+  } catch (err) {
+    if (err.code !== 'ENOENT') throw err;
+    return {};
+  }
+}
+
+/* Option defaults from the "defaults" section of the user's config.json,
+ * overridden by the one in the project's kibob.json. yargs ranks them above
+ * the built-in defaults and below KIBOB_* variables and the command line.
+ */
+function configDefaults() {
+  return {
+    ...readUserConfig().defaults,
+    ...readProjectConfig().defaults,
+  };
+}
+
+// Each option default that is set, with the layer that set it
+function showDefaults() {
+  const layers = {
+    user: readUserConfig().defaults || {},
+    project: readProjectConfig().defaults || {},
+    env: R.fromPairs(
+      Object.keys(process.env)
+        .filter((name) => name.startsWith('KIBOB_'))
+        .map((name) => [
+          name.slice('KIBOB_'.length).toLowerCase().replace(/_/g, '-'),
+          process.env[name],
+        ])
+    ),
+  };
+  const options = {};
+  for (const [source, values] of Object.entries(layers)) {
+    for (const [option, value] of Object.entries(values)) {
+      options[option] = { option, value: JSON.stringify(value), source };
+    }
+  }
+  return R.sortBy(R.prop('option'), R.values(options));
+}
+
+/* The transforms to apply to objects of a type when writing them: the
+ * command line options, overridden by the "*" section and then the type's
+ * own section of "transforms" in kibob.json. Fields in a section's keep list
+ * are taken out of the drop list, so a type can hold on to a field that "*"
+ * drops.
+ */
+function transformsFor(argv, type) {
+  const sections = readProjectConfig().transforms || {};
+  const steps = {
+    drop: argv.drop,
+    expand: argv.expand,
+    multiline: true,
+    markdown: true,
+    normalize: argv.normalize,
+    ...sections['*'],
+    ...sections[type],
+  };
+  const keep = [
+    ...R.pathOr([], ['*', 'keep'], sections),
+    ...R.pathOr([], [type, 'keep'], sections),
+  ];
+  return R.omit(['keep'], {
+    ...steps,
+    drop: R.difference(steps.drop || [], keep),
+  });
+}
+
+/* The effective transforms for each type named in kibob.json, or just the
+ * given type, as they would apply with the export defaults.
+ */
+function showTransforms(argv) {
+  const sections = readProjectConfig().transforms || {};
+  const types = argv.type ? [argv.type] : ['*', ...Object.keys(sections)];
+  const defaults = { drop: volatileFields, expand: true, normalize: true };
+  return R.uniq(types).map((type) => ({
+    type,
+    ...transformsFor({ ...defaults, ...argv }, type),
+  }));
+}
+
+function printTransforms(rules) {
+  printTable(
+    [
+      'type',
+      'drop',
+      'expand',
+      'multiline',
+      'markdown',
+      'normalize',
+      'secrets',
+      'plugins',
+    ],
+    rules.map((rule) => ({
+      ...rule,
+      drop: rule.drop.join(',') || '-',
+      secrets: (rule.secrets || []).join(',') || '-',
+      plugins: (rule.plugins || []).join(',') || '-',
+    }))
+  );
+}
+
+// Headers sent with every Kibana API request
+function kibanaHeaders(argv) {
+  const headers = { 'kbn-xsrf': true };
+  if (argv.apiKey) headers.Authorization = `ApiKey ${argv.apiKey}`;
+  return headers;
+}
+
+let httpAgent;
+let httpsAgent;
+
+// Keep connections open between requests, up to --max-sockets at a time
+const poolOptions = (argv) => ({
+  keepAlive: true,
+  maxSockets: argv.maxSockets || 8,
+});
+
+// HTTPS agent carrying the --ca, --cert, --key and --insecure TLS settings
+function kibanaAgent(argv) {
+  if (!httpsAgent) {
+    const readPem = (file) => file && require('fs').readFileSync(file);
+    httpsAgent = new https.Agent({
+      ...poolOptions(argv),
+      ca: readPem(argv.ca),
+      cert: readPem(argv.cert),
+      key: readPem(argv.key),
+      rejectUnauthorized: !argv.insecure,
+    });
+  }
+  return httpsAgent;
+}
+
+// Plain HTTP agent, pooled the same way as kibanaAgent
+function httpKibanaAgent(argv) {
+  httpAgent = httpAgent || new http.Agent(poolOptions(argv));
+  return httpAgent;
+}
+
+const proxyAgents = {};
+
+/* The proxy to use for a URL: --proxy or KIBANA_PROXY, otherwise the usual
+ * HTTPS_PROXY or HTTP_PROXY, unless the host matches an entry in NO_PROXY.
+ */
+function proxyFor(argv, parsed) {
+  const env = (name) => process.env[name] || process.env[name.toLowerCase()];
+  const noProxy = (env('NO_PROXY') || '')
+    .split(',')
+    .map((entry) => entry.trim().replace(/^\*?\./, ''))
+    .filter((entry) => entry);
+  const bypass = noProxy.some(
+    (entry) =>
+      entry === '*' ||
+      parsed.hostname === entry ||
+      parsed.hostname.endsWith(`.${entry}`)
+  );
+  if (bypass) return undefined;
+  if (argv.proxy) return argv.proxy;
+  return parsed.protocol === 'https:' ? env('HTTPS_PROXY') : env('HTTP_PROXY');
+}
+
+// node-fetch agent option: the proxy, or the TLS settings for https URLs
+function agentFor(argv) {
+  return (parsed) => {
+    const proxy = proxyFor(argv, parsed);
+    if (proxy) {
+      proxyAgents[proxy] = proxyAgents[proxy] || new HttpsProxyAgent(proxy);
+      return proxyAgents[proxy];
+    }
+    return parsed.protocol === 'https:'
+      ? kibanaAgent(argv)
+      : httpKibanaAgent(argv);
+  };
+}
+
+const testedMajors = [7, 8];
+const kibanaVersions = {};
+
+/* Read the Kibana version from /api/status, once per Kibana host. Resolves
+ * to { number, major }, both undefined if the status API can't be read.
+ */
+function kibanaVersion(argv) {
+  const url = new URL(argv.url);
+  url.pathname = '/api/status';
+  url.search = '';
+  const probe = async () => {
+    try {
+      const res = await fetch(url, {
+        headers: kibanaHeaders(argv),
+        agent: agentFor(argv),
+      });
+      const body = await res.json();
+      const number = body.version && body.version.number;
+      if (!number) return {};
+      const major = parseInt(number, 10);
+      logger.verbose(`Kibana ${url.host} is version ${number}`);
+      if (!testedMajors.includes(major)) {
+        logger.warn(`kibob has not been tested against Kibana ${number}`);
+      }
+      return { number, major };
+    } catch (err) {
+      logger.debug(`Could not read the Kibana version: ${err.message}`);
+      return {};
+    }
+  };
+  kibanaVersions[url.origin] = kibanaVersions[url.origin] || probe();
+  return kibanaVersions[url.origin];
+}
+
+/* fetch() through the proxy, with the TLS settings applied to https URLs.
+ * Kibana 8 and later get the public API version header, which serverless
+ * projects require.
+ */
+async function kibanaFetch(argv, url, options) {
+  const headers = { ...options.headers };
+  const { major } = await kibanaVersion(argv);
+  if (major >= 8) headers['Elastic-Api-Version'] = '2023-10-31';
+  return fetch(url, { ...options, headers, agent: agentFor(argv) });
+}
+
+const sleep = (ms) => new Promise((resolve) => setTimeout(resolve, ms));
+const retryStatuses = [429, 502, 503, 504];
+let nextRequestAt = 0;
+
+/* Calls send() to make a request, spacing requests out to --rate-limit and
+ * retrying network errors and throttled or unavailable responses with
+ * exponential backoff and jitter. A Retry-After header takes precedence.
+ */
+async function withRetry(argv, send) {
+  for (let attempt = 1; ; attempt++) {
+    if (argv.rateLimit) {
+      const now = Date.now();
+      const wait = Math.max(0, nextRequestAt - now);
+      nextRequestAt = Math.max(now, nextRequestAt) + 1000 / argv.rateLimit;
+      await sleep(wait);
+    }
+
+    let res, error;
+    try {
+      res = await send();
+    } catch (err) {
+      error = err;
+    }
+    if (!(error || retryStatuses.includes(res.status))) {
+      if ([401, 403].includes(res.status)) fail(exitCodes.auth);
+      return res;
+    }
+    if (attempt > argv.retries) {
+      if (!error) return res;
+      fail(exitCodes.network);
+      throw error;
+    }
+
+    const retryAfter = res && Number(res.headers.get('retry-after'));
+    const backoff = argv.retryDelay * 2 ** (attempt - 1);
+    const delay = retryAfter
+      ? retryAfter * 1000
+      : Math.round(backoff / 2 + (Math.random() * backoff) / 2);
+    const reason = error ? error.message : `${res.status} ${res.statusText}`;
+    logger.warn(`${reason}, retry ${attempt} of ${argv.retries} in ${delay}ms`);
+    await sleep(delay);
+  }
+}
+
+/* Loads saved objects from a saved_objects.ndjson file and calls the Kibana
+ * create saved objects API to import them.
+ * https://www.elastic.co/guide/en/kibana/master/saved-objects-api-import.html
+ */
+
+async function importObjects(
+  argv,
+  content = () => createReadStream(argv.file)
+) {
+  const url = new URL(argv.url);
+  url.search = argv.overwrite ? '?overwrite=true' : '';
+  url.pathname = spacePath(argv, `/api/saved_objects/_import`);
+
+  // The form streams the file, so it has to be rebuilt for every attempt
+  const send = () => {
+    const form = new FormData();
+    form.append('file', content(), {
+      contentType: 'text/plain',
+      name: 'file',
+      filename: argv.file,
+    });
+    return kibanaFetch(argv, url, {
+      method: 'POST',
+      headers: { ...kibanaHeaders(argv), ...form.getHeaders() },
+      body: form,
+    });
+  };
+
+  logger.info('loading saved objects from ' + argv.file);
+  try {
+    const res = await withRetry(argv, send);
+    const json = await res.json();
+    if (res.status !== 200 && libraryMode) {
+      throw apiError(res.status, res.statusText, json);
+    }
+    if (res.status !== 200) {
+      const body = JSON.stringify(json, null, 2);
+      logger.error(`${res.status} ${res.statusText} Error: ${body}`);
+      fail();
+      return { ...importReport({}), status: res.status, message: json.message };
+    }
+
+    const result = importReport(json);
+    logger.info(
+      `${res.status} ${res.statusText} Imported ${result.successCount} ` +
+        `objects, ${result.errors.length} failed`
+    );
+    for (const warning of result.warnings) logger.warn(warning);
+    if (result.errors.length > 0 && argv.failOnError !== false) {
+      fail(exitCodes.partial);
+    }
+    if (result.warnings.length > 0 && argv.failOnWarning) fail();
+    return result;
+  } catch (err) {
+    commandError(err);
+  }
+}
+
+// Summarize an import API response into per-object errors and warnings
+function importReport(body) {
+  const detail = (error) =>
+    error.message ||
+    (error.references || []).map((ref) => `${ref.type}/${ref.id}`).join(' ');
+  return {
+    success: Boolean(body.success),
+    successCount: body.successCount || 0,
+    errors: (body.errors || []).map((err) => ({
+      type: err.type,
+      id: err.id,
+      title: err.title || (err.meta && err.meta.title),
+      error: err.error.type,
+      detail: detail(err.error),
+    })),
+    warnings: (body.warnings || []).map((warning) => warning.message),
+    types: importCounts(body),
+  };
+}
+
+// Created, updated and failed counts per type from an import response
+function importCounts(body) {
+  const counts = {};
+  const add = (type, field) => {
+    counts[type] = counts[type] || { created: 0, updated: 0, failed: 0 };
+    counts[type][field]++;
+  };
+  for (const obj of body.successResults || []) {
+    add(obj.type, obj.overwrite ? 'updated' : 'created');
+  }
+  for (const err of body.errors || []) add(err.type, 'failed');
+  return counts;
+}
+
+// Print a table of the objects that failed to import
+function printImport(result) {
+  if (!result || result.errors.length === 0) return;
+  printTable(['type', 'id', 'title', 'error', 'detail'], result.errors);
+}
+
+// Read a --remap or --values file, a JSON object of names to values
+async function readJsonFile(filename) {
+  return JSON.parse(await fs.readFile(filename, 'utf8'));
+}
+
+/* The value of a ${NAME} secret placeholder, from the environment variable
+ * NAME or else the --secrets file.
+ */
+async function secretLookup(argv) {
+  const secrets = argv.secrets ? await readJsonFile(argv.secrets) : {};
+  return (name) => {
+    if (R.has(name, process.env)) return process.env[name];
+    if (R.has(name, secrets)) return secrets[name];
+    throw new KibobError(
+      'MISSING_SECRET',
+      `Set ${name} in the environment or the --secrets file`,
+      { secret: name }
+    );
+  };
+}
+
+/* Rewrite objects on their way to Kibana with --remap ids and --values, and
+ * fill in the secrets scrubbed on pull.
+ */
+async function pushTransform(argv) {
+  const ids = argv.remap && (await readJsonFile(argv.remap));
+  const values = argv.values && (await readJsonFile(argv.values));
+  const slugs = argv.slugs;
+  const secret = await secretLookup(argv);
+  return (obj) => {
+    const steps = transformsFor(argv, obj.type);
+    obj = plugins.push(steps.plugins, obj);
+    const remapped = ids ? transforms.remapIds(obj, ids) : obj;
+    const linked = slugs ? transforms.rewriteSlugs(remapped, slugs) : remapped;
+    const filled = values ? transforms.fillTemplate(linked, values) : linked;
+    return transforms.injectSecrets(filled, steps.secrets || [], secret);
+  };
+}
+
+/* Replace the values at the type's "secrets" paths with ${NAME} placeholders
+ * on objects pulled. With --template, turn known --values back into
+ * {{ name }} placeholders, at the "templatePaths" of kibob.json if it has
+ * any. Then run the type's plugins over them.
+ */
+async function pullTransform(argv) {
+  const values =
+    argv.template && argv.values && (await readJsonFile(argv.values));
+  const paths = readProjectConfig().templatePaths || [];
+  return (obj) => {
+    const steps = transformsFor(argv, obj.type);
+    const scrubbed = transforms.scrubSecrets(obj, steps.secrets || []);
+    const templated = values
+      ? transforms.templateObject(scrubbed, values, paths)
+      : scrubbed;
+    return plugins.pull(steps.plugins, templated);
+  };
+}
+
+// Whether an object has one of the --types, which may be comma separated
+function hasType(argv, obj) {
+  if (!argv.types) return true;
+  return R.chain((type) => type.split(','), argv.types).includes(obj.type);
+}
+
+/* The import file's objects filtered by --types and rewritten with --remap
+ * and --values.
+ */
+async function importContent(argv) {
+  const transform = await pushTransform(argv);
+  const objects = [];
+  for await (const obj of readNdjson(argv.file)) {
+    if (obj.type && hasType(argv, obj)) objects.push(transform(obj));
+  }
+  return objects;
+}
+
+/* The import file's objects, filtered and rewritten like importContent, read
+ * a tier of types at a time so the file is never held in memory.
+ */
+async function* streamContent(argv) {
+  const transform = await pushTransform(argv);
+  for (let tier = 0; tier < graph.tierCount; tier++) {
+    for await (const obj of readNdjson(argv.file)) {
+      if (!obj.type || !hasType(argv, obj)) continue;
+      if (graph.typeTier(obj.type) === tier) yield transform(obj);
+    }
+  }
+}
+
+/* Call fn on each item, --concurrency or limit at a time, so requests per
+ * type or per resource to one Kibana overlap. Resolves to the results in the
+ * order of the items.
+ */
+async function mapLimit(limit, items, fn) {
+  const results = [];
+  let next = 0;
+  const worker = async () => {
+    while (next < items.length) {
+      const i = next++;
+      results[i] = await fn(items[i], i);
+    }
+  };
+  const workers = Math.max(1, Math.min(limit || 1, items.length));
+  await Promise.all(R.range(0, workers).map(worker));
+  return results;
+}
+
+// Group the objects of an (async) iterable into arrays of up to size objects
+async function* batchesOf(size, objects) {
+  let batch = [];
+  for await (const obj of objects) {
+    batch.push(obj);
+    if (batch.length >= size) {
+      yield batch;
+      batch = [];
+    }
+  }
+  if (batch.length > 0) yield batch;
+}
+
+/* Import objects --batch-size at a time, so large sets stay under Kibana's
+ * savedObjects.maxImportExportSize, and add the results of every batch up
+ * into one report. An array is put in reference order first, so objects only
+ * refer to ones in the same or an earlier batch. An async iterable, like
+ * streamContent, is imported in the order it yields objects. imported is
+ * called with the objects of each batch that Kibana took. When a batch cannot
+ * be sent, the report of the batches before it is returned with success
+ * false, or attached to the thrown error as its result.
+ */
+async function importBatches(argv, objects, imported = async () => {}) {
+  const ordered = Array.isArray(objects)
+    ? graph.sortByReferences(objects)
+    : objects;
+  let total = importReport({ success: true });
+  let i = 0;
+  for await (const batch of batchesOf(argv.batchSize, ordered)) {
+    i++;
+    if (i > 1 || batch.length === argv.batchSize) {
+      logger.info(`Importing batch ${i}`);
+    }
+    const ndjson = batch.map((obj) => JSON.stringify(obj) + '\n').join('');
+    let result;
+    try {
+      result = await importObjects(argv, () => Buffer.from(ndjson));
+    } catch (err) {
+      throw Object.assign(err, { result: total });
+    }
+    if (!result) return { ...total, success: false };
+    if (result.successCount > 0) {
+      const failed = result.errors.map(objectKey);
+      await imported(batch.filter((obj) => !failed.includes(objectKey(obj))));
+    }
+    total = {
+      ...result,
+      success: total.success && result.success,
+      successCount: total.successCount + result.successCount,
+      errors: [...total.errors, ...result.errors],
+      warnings: [...total.warnings, ...result.warnings],
+      types: R.mergeWith(R.mergeWith(R.add), total.types, result.types),
+    };
+    // a rejected request, like one too large, fails for the rest too
+    if (result.status) break;
+  }
+  return total;
+}
+
+// Stream the non-empty lines of a file without reading it all into memory
+async function* readLines(filename) {
+  const lines = readline.createInterface({
+    input: createReadStream(filename),
+    crlfDelay: Infinity,
+  });
+  for await (const line of lines) {
+    if (line.trim()) yield line;
+  }
+}
+
+// Stream the objects in an .ndjson file
+async function* readNdjson(filename) {
+  for await (const line of readLines(filename)) yield JSON.parse(line);
+}
+
+// Write an (async) iterable of objects to an .ndjson file, one line at a time
+async function writeNdjson(filename, objects) {
+  const temp = state.tempFile(filename);
+  const out = createWriteStream(temp);
+  let count = 0;
+  for await (const obj of objects) {
+    if (!out.write(JSON.stringify(obj) + '\n')) await once(out, 'drain');
+    count++;
+  }
+  out.end();
+  await once(out, 'finish');
+  await fs.rename(temp, filename);
+  return count;
+}
+
+// Fetch Kibana's current copy of each object, missing ones have an error
+// https://www.elastic.co/guide/en/kibana/current/saved-objects-api-bulk-get.html
+async function bulkGetObjects(argv, objects) {
+  const url = new URL(argv.url);
+  url.pathname = spacePath(argv, '/api/saved_objects/_bulk_get');
+
+  const options = {
+    method: 'POST',
+    headers: { ...kibanaHeaders(argv), 'Content-Type': 'application/json' },
+    body: JSON.stringify(objects.map(R.pick(['type', 'id']))),
+  };
+
+  const res = await withRetry(argv, () => kibanaFetch(argv, url, options));
+  const body = await res.json();
+  if (res.status !== 200) {
+    throw apiError(res.status, res.statusText, body);
+  }
+  return body.saved_objects;
+}
+
+/* Work out what importing a file would do, without changing anything. The
+ * objects are compared as they would be sent, after --remap, --values and
+ * the plugins.
+ */
+async function planImport(argv) {
+  const content = R.pick(['attributes', 'references']);
+  const counts = { create: 0, overwrite: 0, conflict: 0, unchanged: 0 };
+
+  try {
+    const objects = await importContent(argv);
+    const current = await bulkGetObjects(argv, objects);
+
+    const actions = objects.map((obj, i) => {
+      let action = 'create';
+      if (!current[i].error) {
+        if (R.equals(content(obj), content(current[i]))) action = 'unchanged';
+        else action = argv.overwrite ? 'overwrite' : 'conflict';
+      }
+      counts[action]++;
+      return { action, type: obj.type, id: obj.id, title: objectTitle(obj) };
+    });
+    return { counts, objects: actions };
+  } catch (err) {
+    commandError(err);
+  }
+}
+
+// Print an import plan, one object per line
+function printPlan({ counts, objects }) {
+  const symbols = {
+    create: '+',
+    overwrite: '~',
+    conflict: '!',
+    unchanged: '=',
+  };
+  for (const obj of objects) {
+    console.log(`${symbols[obj.action]} ${obj.type}/${obj.id} "${obj.title}"`);
+  }
+  logger.info(
+    `Plan: ${counts.create} to create, ${counts.overwrite} to overwrite, ` +
+      `${counts.conflict} conflicting, ${counts.unchanged} unchanged`
+  );
+}
+
+/* Find the Kibana saved objects of --types that match --search. They are
+ * read with the export API, a type per request and --concurrency requests at
+ * a time, which pages through them with a point-in-time rather than the page
+ * numbers of the find API, which cannot go past Elasticsearch's 10,000 hits.
+ * Each response is read a line at a time.
+ * https://www.elastic.co/guide/en/kibana/current/saved-objects-api-export.html
+ */
+async function findObjects(argv) {
+  const url = new URL(argv.url);
+  url.pathname = spacePath(argv, '/api/saved_objects/_export');
+
+  const findType = async (type) => {
+    const options = {
+      method: 'POST',
+      headers: { ...kibanaHeaders(argv), 'Content-Type': 'application/json' },
+      body: JSON.stringify({
+        type: [type],
+        search: argv.search,
+        excludeExportDetails: true,
+      }),
+    };
+    const res = await withRetry(argv, () => kibanaFetch(argv, url, options));
+    if (res.status !== 200) {
+      const body = await res.text();
+      const error = body.startsWith('{') ? JSON.parse(body) : body;
+      throw apiError(res.status, res.statusText, error);
+    }
+    const found = [];
+    const lines = readline.createInterface({
+      input: res.body,
+      crlfDelay: Infinity,
+    });
+    for await (const line of lines) {
+      if (line.trim()) found.push(JSON.parse(line));
+    }
+    return found.filter((obj) => obj.type);
+  };
+
+  let objects;
+  try {
+    const types = R.chain((type) => type.split(','), argv.types);
+    const found = await mapLimit(argv.concurrency, types, findType);
+    objects = R.unnest(found);
+    logger.info(`Found: ${objects.length} objects`);
+  } catch (err) {
+    commandError(err);
+    return;
+  }
+  return objects;
+}
+
+// Whether the letters of filter appear in text in order, ignoring case
+function fuzzyMatch(filter, text) {
+  let i = 0;
+  for (const char of text.toLowerCase()) {
+    if (char === filter[i]) i++;
+  }
+  return i === filter.length;
+}
+
+// Expand a selection like "1,3-5" into the list numbers it names
+function selectedNumbers(input) {
+  return R.chain((part) => {
+    const [from, to = from] = part.split('-').map(Number);
+    return R.range(from, to + 1);
+  }, input.split(/[\s,]+/).filter((part) => part));
+}
+
+/* Let the user pick objects found with --search and --types: typing text
+ * fuzzy filters the list, typing numbers like 1,3-5 selects from it, and an
+ * empty line finishes. Returns the picks as type=id pairs for --objects.
+ * Prompts go to stderr so stdout stays clean for --output json.
+ */
+async function pickObjects(argv) {
+  const found = (await findObjects(argv)) || [];
+  const prompt = readline.createInterface({
+    input: process.stdin,
+    output: process.stderr,
+  });
+  const question = (text) =>
+    new Promise((resolve) => prompt.question(text, resolve));
+  const picked = new Set();
+  let shown = found;
+
+  const list = () =>
+    shown.forEach((obj, i) => {
+      const mark = picked.has(obj) ? '*' : ' ';
+      const title = objectTitle(obj);
+      process.stderr.write(`${mark}${i + 1}\t${obj.type}\t${title}\n`);
+    });
+
+  list();
+  for (;;) {
+    const input = (await question(`${picked.size} picked> `)).trim();
+    if (!input) break;
+    if (/^[\d,\s-]+$/.test(input)) {
+      for (const n of selectedNumbers(input)) {
+        if (shown[n - 1]) picked.add(shown[n - 1]);
+      }
+    } else {
+      const filter = input.toLowerCase();
+      shown = found.filter((obj) =>
+        fuzzyMatch(filter, `${obj.type} ${objectTitle(obj)}`)
+      );
+    }
+    list();
+  }
+  prompt.close();
+  return [...picked].map((obj) => `${obj.type}=${obj.id}`);
+}
+
+// Tags and canvas workpads have a name rather than a title
+function objectTitle(obj) {
+  const attributes = obj.attributes || {};
+  return attributes.title || attributes.name;
+}
+
+// Prefix an API path with the Kibana space, if one was given
+function spacePath(argv, pathname) {
+  return argv.space ? `/s/${argv.space}${pathname}` : pathname;
+}
+
+/* Map layers backed by a data view that is not among the objects, which
+ * would break when imported elsewhere. The documents in the data view,
+ * like an uploaded GeoJSON file, are never exported and have to be loaded
+ * into the other cluster separately.
+ */
+function mapWarnings(objects) {
+  const keys = new Set(objects.map(objectKey));
+  const warnings = [];
+  for (const map of objects.filter((obj) => obj.type === 'map')) {
+    const refs = R.indexBy(R.prop('name'), map.references || []);
+    let layers = R.pathOr([], ['attributes', 'layerListJSON'], map);
+    if (typeof layers === 'string') layers = JSON.parse(layers);
+    for (const layer of layers) {
+      const source = layer.sourceDescriptor || {};
+      const ref = refs[source.indexPatternRefName];
+      if (ref && !keys.has(`index-pattern/${ref.id}`)) {
+        warnings.push(
+          `Map "${objectTitle(map)}" layer "${layer.label || layer.id}" ` +
+            `uses data view ${ref.id}, which is not exported`
+        );
+      }
+    }
+  }
+  return warnings;
+}
+
+// Write an array of JSON objects into an .ndjson file
+async function saveObjects(argv, saved_objects) {
+  const filename = argv.file;
+  try {
+    const template = await pullTransform(argv);
+    const transform = (obj) => {
+      const steps = transformsFor(argv, obj.type);
+      obj = template(transforms.dropFields(obj, steps.drop || []));
+      return steps.normalize ? transforms.normalizeObject(obj) : obj;
+    };
+    for (const warning of mapWarnings(saved_objects)) logger.warn(warning);
+    const sorted = graph.sortByReferences(saved_objects.map(transform));
+    await writeNdjson(filename, sorted);
+    logger.info(`Saved ${saved_objects.length} objects to ${filename}`);
+    return { file: filename, count: saved_objects.length };
+  } catch (err) {
+    logger.error(`${filename}: [${err.name}] ${err.message}`);
+    throw err;
+  }
+}
+
+// Export Kibana saved objects from the _export API
+// https://www.elastic.co/guide/en/kibana/current/saved-objects-api-export.html
+async function exportObjects(argv) {
+  const url = new URL(argv.url);
+  url.pathname = spacePath(argv, '/api/saved_objects/_export');
+
+  // --objects takes type=id pairs, space or comma separated
+  const objects = R.chain((pair) => pair.split(','), argv.objects)
+    .filter((pair) => pair)
+    .map((pair) => {
+      const [type, id] = pair.split('=');
+      return { type, id };
+    });
+
+  // ask for --batch-size objects at a time, objects referenced from more
+  // than one batch come back more than once
+  const exported = [];
+  try {
+    for (const batch of R.splitEvery(argv.batchSize || 1000, objects)) {
+      const options = {
+        method: 'POST',
+        headers: {
+          ...kibanaHeaders(argv),
+          'Content-Type': 'application/json',
+        },
+        body: JSON.stringify({
+          objects: batch,
+          includeReferencesDeep: argv.references,
+        }),
+      };
+      const res = await withRetry(argv, () => kibanaFetch(argv, url, options));
+      const body = await res.text();
+      if (res.status !== 200) {
+        const error = body.startsWith('{') ? JSON.parse(body) : body;
+        throw apiError(res.status, res.statusText, error);
+      }
+      // The last line is an export summary rather than a saved object
+      exported.push(
+        ...body
+          .split('\n')
+          .filter((line) => line.trim())
+          .map((line) => JSON.parse(line))
+          .filter((obj) => obj.type)
+      );
+    }
+  } catch (err) {
+    commandError(err);
+    return;
+  }
+  const unique = R.uniqBy(objectKey, exported);
+  logger.info(`Exported: ${unique.length} objects`);
+  return unique;
+}
+
+// Convert .ndjson file into separate .json files
+async function unbundleObjects(argv) {
+  const path = argv.dir;
+  let count = 0;
+  const skipped = [];
+  try {
+    await fs.mkdir(path, { recursive: true });
+    await ignore.writeStarterIgnore(path);
+    await backupUnsaved(argv, path);
+    const names = await readFileNames(path);
+    const dirty = argv.force ? new Set() : await dirtyFiles(path);
+    const template = await pullTransform(argv);
+
+    for await (const line of readLines(argv.file)) {
+      try {
+        const json = JSON.parse(line);
+        if (json.type) {
+          const name = fileNameFor(argv, names, json);
+          if (dirty.has(name)) {
+            logger.error(
+              `${path}/${name} has uncommitted changes, ` +
+                'commit or stash them, or use --force to overwrite'
+            );
+            fail();
+            skipped.push(name);
+            continue;
+          }
+          await writeObjectFile(argv, path, template(json), names);
+          count++;
+        }
+      } catch (SyntaxError) {
+        logger.debug(`Failed to parse: ${SyntaxError}`);
+      }
+    }
+    await saveFileNames(path, names);
+    return { dir: path, count, skipped };
+  } catch (err) {
+    commandError(err);
+  }
+}
+
+// App paths of Kibana share links and the type of object each one opens
+const shareLinks = [
+  [/^\/app\/dashboards#\/view\/([^/?]+)/, 'dashboard'],
+  [/^\/app\/kibana#\/dashboard\/([^/?]+)/, 'dashboard'],
+  [/^\/app\/visualize#\/edit\/([^/?]+)/, 'visualization'],
+  [/^\/app\/lens#\/edit\/([^/?]+)/, 'lens'],
+  [/^\/app\/discover#\/view\/([^/?]+)/, 'search'],
+  [/^\/app\/maps\/map\/([^/?#]+)/, 'map'],
+  [/^\/app\/canvas#\/workpad\/([^/?]+)/, 'canvas-workpad'],
+];
+
+// Locator state fields of short URLs that hold the id of a saved object
+const locatorIds = { dashboardId: 'dashboard', savedSearchId: 'search' };
+
+/* Find the saved object a share link points at, as { type, id }. Short
+ * /goto/ links are resolved through the short URL API, or by following the
+ * redirect on Kibana versions before 8.
+ */
+async function resolveShareLink(argv, link) {
+  const pathname = link.pathname.replace(/^\/s\/[^/]+/, '');
+  const short = pathname.match(/^\/goto\/([^/?]+)/);
+  if (!short) {
+    for (const [pattern, type] of shareLinks) {
+      const match = (pathname + link.hash).match(pattern);
+      if (match) return { type, id: decodeURIComponent(match[1]) };
+    }
+    throw new KibobError('NOT_FOUND', `No saved object in ${link.href}`);
+  }
+
+  const api = spacePath(argv, `/api/short_url/${short[1]}`);
+  const { status, statusText, body } = await getJson(argv, api);
+  if (status === 200) {
+    const state = R.pathOr({}, ['locator', 'state'], body);
+    for (const [field, type] of Object.entries(locatorIds)) {
+      if (state[field]) return { type, id: state[field] };
+    }
+    throw new KibobError('NOT_FOUND', `No saved object in ${link.href}`);
+  }
+  if (status !== 404) throw apiError(status, statusText, body);
+
+  const goto = new URL(spacePath(argv, pathname), argv.url);
+  const options = { headers: kibanaHeaders(argv), redirect: 'manual' };
+  const res = await withRetry(argv, () => kibanaFetch(argv, goto, options));
+  const location = res.headers.get('location');
+  if (!location) throw apiError(res.status, res.statusText, {});
+  return resolveShareLink(argv, new URL(location, argv.url));
+}
+
+/* Export the object behind a Kibana share link, and what it references, and
+ * write it into the directory as unbundle would.
+ */
+async function addFromUrl(argv) {
+  try {
+    const link = new URL(argv.fromUrl);
+    if (!argv.url) argv.url = argv.u = link.origin;
+    const space = link.pathname.match(/^\/s\/([^/]+)/);
+    if (!argv.space && space) argv.space = space[1];
+
+    const { type, id } = await resolveShareLink(argv, link);
+    logger.info(`Adding ${type}/${id}`);
+    const pair = `${type}=${id}`;
+    const objects = await exportObjects({ ...argv, objects: [pair] });
+    if (!objects) return;
+
+    const files = await pullObjects(argv, objects);
+    logger.info(`Added ${files.length} objects to ${argv.dir}`);
+    return { dir: argv.dir, type, id, files };
+  } catch (err) {
+    commandError(err);
+  }
+}
+
+/* Write exported objects into the directory as export and unbundle would,
+ * skipping files with uncommitted changes unless --force. Returns the names
+ * of the files written.
+ */
+async function pullObjects(argv, objects) {
+  const dir = argv.dir;
+  await fs.mkdir(dir, { recursive: true });
+  await backupUnsaved(argv, dir);
+  const names = await readFileNames(dir);
+  const dirty = argv.force ? new Set() : await dirtyFiles(dir);
+  const template = await pullTransform(argv);
+  const files = [];
+  for (const obj of objects) {
+    const name = fileNameFor(argv, names, obj);
+    if (dirty.has(name)) {
+      logger.error(
+        `${dir}/${name} has uncommitted changes, ` +
+          'commit or stash them, or use --force to overwrite'
+      );
+      fail();
+      continue;
+    }
+    const steps = transformsFor(argv, obj.type);
+    const kept = transforms.dropFields(obj, steps.drop || []);
+    files.push(await writeObjectFile(argv, dir, template(kept), names));
+  }
+  await saveFileNames(dir, names);
+  await state.recordPull(lockTarget(argv), objects.length);
+  return files;
+}
+
+/* Start a project from a live Kibana space: pick the space if --space is
+ * not given, export the objects of --types and everything they reference
+ * into the directory, and record them in the lockfile. kibob.json gets the
+ * URL and space as defaults so later commands need no flags.
+ */
+async function initProject(argv) {
+  try {
+    if (!argv.space) argv.space = await pickSpace(argv);
+    const found = await findObjects(argv);
+    if (!found) return;
+    const pairs = found.map((obj) => `${obj.type}=${obj.id}`);
+    const objects = pairs.length
+      ? await exportObjects({ ...argv, objects: pairs, references: true })
+      : [];
+    if (!objects) return;
+
+    const files = await pullObjects(argv, objects);
+    await ignore.writeStarterIgnore(argv.dir);
+    await updateLock(argv, objects);
+
+    const url = new URL(argv.url);
+    url.username = url.password = '';
+    const config = {
+      defaults: R.pickBy((value) => value, {
+        url: url.toString(),
+        space: argv.space,
+        dir: argv.dir,
+      }),
+    };
+    try {
+      const data = JSON.stringify(config, null, 2) + '\n';
+      await fs.writeFile(projectConfigFile, data, { flag: 'wx' });
+      logger.info(`Wrote ${projectConfigFile}`);
+    } catch (err) {
+      if (err.code !== 'EEXIST') throw err;
+      logger.info(`Kept the existing ${projectConfigFile}`);
+    }
+
+    logger.info(`Pulled ${files.length} objects into ${argv.dir}`);
+    return { space: argv.space || 'default', dir: argv.dir, files };
+  } catch (err) {
+    commandError(err);
+  }
+}
+
+// Ask which space to start from when Kibana has more than the default one
+async function pickSpace(argv) {
+  const { status, statusText, body } = await getJson(argv, '/api/spaces/space');
+  if (status !== 200) throw apiError(status, statusText, body);
+  if (body.length < 2 || !process.stdin.isTTY) return;
+
+  body.forEach((space, i) =>
+    process.stderr.write(`${i + 1}\t${space.id}\t${space.name}\n`)
+  );
+  const prompt = readline.createInterface({
+    input: process.stdin,
+    output: process.stderr,
+  });
+  const answer = await new Promise((resolve) =>
+    prompt.question('Space number, empty for default: ', resolve)
+  );
+  prompt.close();
+  const space = body[Number(answer.trim()) - 1];
+  return space && space.id !== 'default' ? space.id : undefined;
+}
+
+// Files directly in dir with uncommitted git changes, none outside a repo
+async function dirtyFiles(dir) {
+  try {
+    const status = await git(dir, 'status', '--porcelain', '-z', '--', '.');
+    return new Set(
+      status
+        .split('\0')
+        .filter((entry) => entry.length > 3)
+        .map((entry) => path.basename(entry.slice(3)))
+    );
+  } catch (err) {
+    return new Set();
+  }
+}
+
+/* Back up the files in dir that writing objects into it may overwrite and
+ * git cannot bring back: those with uncommitted changes when --force lets
+ * them be overwritten, or all of them outside a git repository.
+ */
+async function backupUnsaved(argv, dir) {
+  let names;
+  try {
+    await git(dir, 'rev-parse', '--is-inside-work-tree');
+    names = argv.force ? [...(await dirtyFiles(dir))] : [];
+  } catch (err) {
+    names = await fs.readdir(dir);
+  }
+  const files = names.map((name) => `${dir}/${name}`);
+  const { backup, count } = await state.backupFiles(files);
+  if (count > 0) logger.info(`Backed up ${count} files in ${dir} to ${backup}`);
+}
+
+// Copy the files of a backup, the latest by default, back where they were
+async function restoreBackup(argv) {
+  try {
+    const restored = await state.restoreBackup(argv.backup);
+    for (const file of restored.files) logger.verbose(`Restored ${file}`);
+    logger.info(
+      `Restored ${restored.files.length} files from ${restored.backup}`
+    );
+    return restored;
+  } catch (err) {
+    commandError(err);
+  }
+}
+
+const statusWords = { A: 'added', M: 'changed', D: 'removed', R: 'renamed' };
+
+/* Stage everything that changed in the directory and commit just that, with
+ * a message counting the files added, changed and removed, and where they
+ * were pulled from, followed by the list of files.
+ */
+async function commitPull(argv, source) {
+  try {
+    await git(argv.dir, 'add', '-A', '--', '.');
+    const staged = (
+      await git(argv.dir, 'diff', '--cached', '--name-status', '--', '.')
+    )
+      .split('\n')
+      .filter((line) => line.trim())
+      .map((line) => {
+        const [status, ...files] = line.split('\t');
+        const word = statusWords[status[0]] || 'changed';
+        return { word, file: files[files.length - 1] };
+      });
+    if (staged.length === 0) {
+      logger.info(`Nothing changed in ${argv.dir} to commit`);
+      return;
+    }
+    const counts = R.countBy(R.prop('word'), staged);
+    const summary = Object.values(statusWords)
+      .filter((word) => counts[word])
+      .map((word) => `${counts[word]} ${word}`)
+      .join(', ');
+    const body = staged.map(({ word, file }) => `${word} ${file}`).join('\n');
+    const subject = `Pull from ${source}: ${summary}`;
+    await git(argv.dir, 'commit', '-m', subject, '-m', body, '--', '.');
+    logger.info(`Committed: ${subject}`);
+  } catch (err) {
+    logger.error(`Could not commit ${argv.dir}: ${err.message}`);
+  }
+}
+
+// Mapping of type/id to file name, kept so that objects renamed in Kibana
+// keep writing to the same file
+const fileNamesFile = '.kibob-files';
+
+async function readFileNames(dir) {
+  try {
+    return JSON.parse(await fs.readFile(`${dir}/${fileNamesFile}`, 'utf8'));
+  } catch (err) {
+    if (err.code === 'ENOENT') return {};
+    throw err;
+  }
+}
+
+async function saveFileNames(dir, names) {
+  const sorted = transforms.normalizeObject(names);
+  const data = JSON.stringify(sorted, null, 2);
+  await state.writeAtomic(`${dir}/${fileNamesFile}`, data);
+}
+
+/* Pick the file name for an object: the one it already has in names, or one
+ * rendered from the --filename template. {title}, {type}, {id} and
+ * {id_short} are replaced, and the short id is appended when the name is
+ * already taken by another object.
+ */
+function fileNameFor(argv, names, obj) {
+  const key = `${obj.type}/${obj.id}`;
+  if (names[key]) return names[key];
+
+  const fields = {
+    title: String(objectTitle(obj))
+      .replace(/[/\\]/g, '_')
+      .replace(/^\.+/, '_'),
+    type: safeSegment(obj.type),
+    id: safeSegment(obj.id),
+    id_short: safeSegment(String(obj.id).slice(0, 8)),
+  };
+  let name = argv.filename.replace(/\{(\w+)\}/g, (match, field) =>
+    field in fields ? fields[field] : match
+  );
+  if (Object.values(names).includes(name)) {
+    const ext = path.extname(name);
+    name = `${path.basename(name, ext)}-${fields.id_short}${ext}`;
+  }
+  names[key] = name;
+  return name;
+}
+
+// Write a saved object to its own pretty-printed .json file in dir
+async function writeObjectFile(argv, dir, obj, names) {
+  const filename = fileNameFor(argv, names, obj);
+  const assetDir = `${path.basename(filename, '.json')}.assets`;
+  if (obj.type === 'canvas-workpad') {
+    obj = await canvas.extractAssets(obj, dir, assetDir);
+  }
+  const steps = transformsFor(argv, obj.type);
+  if (steps.expand) obj = transforms.expandJsonStrings(obj, steps);
+  if (steps.expand && steps.markdown) {
+    obj = await markdown.extractMarkdown(obj, dir, assetDir);
+  }
+  if (steps.normalize) obj = transforms.normalizeObject(obj);
+  logger.debug(filename);
+  const data = new Uint8Array(Buffer.from(JSON.stringify(obj, null, 2)));
+  await state.writeAtomic(resolveInside(dir, filename), data);
+  return filename;
+}
+
+// Read a .json file written by writeObjectFile back into a saved object
+// A file whose object "extends" a shared file is read as the shared object
+// with the file's own fields, like its space specific id, on top
+async function readObjectFile(dir, name) {
+  const buffer = await fs.readFile(`${dir}/${name}`, 'binary');
+  const json = JSON.parse(buffer);
+  if (json.extends) {
+    const shared = path.join(dir, json.extends);
+    const sharedDir = path.dirname(shared);
+    const base = await readObjectFile(sharedDir, path.basename(shared));
+    return { ...base, ...R.omit(['extends'], json) };
+  }
+  const parsed = await markdown.inlineMarkdown(json, dir);
+  return canvas.inlineAssets(transforms.compactJsonStrings(parsed), dir);
+}
+
+/* Store objects that are the same in several directories, usually one per
+ * space, once in the --shared directory. Each directory keeps a small file
+ * that extends the shared one with its own id, so bundles still get a copy
+ * per space.
+ */
+async function dedupeObjects(argv) {
+  try {
+    const copies = [];
+    for (const dir of argv.dirs) {
+      for (const obj of await readObjects(dir)) {
+        const text = await fs.readFile(`${dir}/${obj.file}`, 'utf8');
+        if (JSON.parse(text).extends) continue;
+        copies.push({ dir, obj: R.omit(['file'], obj), file: obj.file });
+      }
+    }
+
+    await fs.mkdir(argv.shared, { recursive: true });
+    const names = await readFileNames(argv.shared);
+    const groups = R.groupBy(({ obj }) => objectHash(obj), copies);
+    const shared = [];
+    for (const group of R.values(groups)) {
+      if (R.uniq(group.map(R.prop('dir'))).length < 2) continue;
+      const [first] = group;
+      const file = await writeObjectFile(argv, argv.shared, first.obj, names);
+      for (const { dir, obj, file: name } of group) {
+        const stub = {
+          type: obj.type,
+          id: obj.id,
+          extends: path.relative(dir, `${argv.shared}/${file}`),
+        };
+        await removeObjectFile(dir, name);
+        await state.writeAtomic(
+          `${dir}/${name}`,
+          JSON.stringify(stub, null, 2)
+        );
+      }
+      logger.info(`Shared ${argv.shared}/${file} by ${group.length} copies`);
+      shared.push({ file, copies: group.length });
+    }
+    await saveFileNames(argv.shared, names);
+    return shared;
+  } catch (err) {
+    commandError(err);
+  }
+}
+
+// Convert directory of .json files into single .ndjson
+async function bundleObjects(argv) {
+  const transform = await pushTransform(argv);
+  try {
+    if (argv.tag) await checkCommitted(argv);
+    // note the tier of each file's type, then read the files again a tier
+    // at a time, so only their names are held in memory
+    const tiers = R.times(() => [], graph.tierCount);
+    for (const name of await jsonFiles(argv.dir)) {
+      const obj = await readObjectFile(argv.dir, name);
+      if (hasType(argv, obj)) tiers[graph.typeTier(obj.type)].push(name);
+    }
+    const types = [];
+    async function* objects() {
+      for (const name of R.chain((names) => names.sort(), tiers)) {
+        logger.debug(`Bundling '${argv.dir}/${name}'`);
+        const obj = transform(await readObjectFile(argv.dir, name));
+        types.push(obj.type);
+        yield obj;
+      }
+    }
+
+    const i = await writeNdjson(argv.file, objects());
+    const manifest = await writeManifest(argv.file, types);
+    logger.info(`Wrote ${i} objects to ${argv.file}`);
+    if (argv.changelog) await writeChangelog(argv);
+    if (argv.tag) await tagRelease(argv, manifest);
+    return { file: argv.file, count: i, sha256: manifest.sha256 };
+  } catch (err) {
+    commandError(err);
+  }
+}
+
+const changeHeadings = {
+  added: 'Added',
+  modified: 'Changed',
+  deleted: 'Removed',
+};
+
+// A changelog section listing changed objects by title, grouped by change
+function changelogSection(title, since, changed) {
+  const lines = [`## ${title}`, '', `Changes since ${since}.`, ''];
+  if (changed.length === 0) lines.push('No changes to saved objects.', '');
+  for (const [change, heading] of Object.entries(changeHeadings)) {
+    const objects = R.sortBy(
+      (obj) => `${obj.type} ${obj.title || obj.id}`,
+      changed.filter((obj) => obj.change === change)
+    );
+    if (objects.length === 0) continue;
+    lines.push(`### ${heading}`, '');
+    for (const obj of objects) {
+      lines.push(`- ${obj.type}: ${obj.title || obj.id}`);
+    }
+    lines.push('');
+  }
+  return lines.join('\n');
+}
+
+/* Add a section to the top of --changelog on the objects added, changed
+ * and removed in the directory since --since, the latest git tag by default,
+ * below the file's own # heading.
+ */
+async function writeChangelog(argv) {
+  let since = argv.since;
+  if (!since) {
+    try {
+      since = (await git(argv.dir, 'describe', '--tags', '--abbrev=0')).trim();
+    } catch (err) {
+      throw new KibobError(
+        'NOT_FOUND',
+        'No git tag to compare to, use --since'
+      );
+    }
+  }
+  const changed = await diffDirectory({ ...argv, from: since, to: undefined });
+  if (!changed) return;
+  const title =
+    argv.release || argv.tag || new Date().toISOString().slice(0, 10);
+  let text = '';
+  try {
+    text = await fs.readFile(argv.changelog, 'utf8');
+  } catch (err) {
+    if (err.code !== 'ENOENT') throw err;
+  }
+  const heading = (text.match(/^# .*\n+/) || ['# Changelog\n\n'])[0];
+  const rest = text.startsWith(heading) ? text.slice(heading.length) : text;
+  const section = changelogSection(title, since, changed);
+  await state.writeAtomic(argv.changelog, `${heading}${section}\n${rest}`);
+  logger.info(
+    `Added ${changed.length} changes since ${since} to ${argv.changelog}`
+  );
+}
+
+/* Refuse to tag a release when the git tree has changes besides the bundle
+ * and its manifest, as the tagged commit would not hold what was bundled.
+ */
+async function checkCommitted(argv) {
+  const outputs = [argv.file, manifestFile(argv.file)].map(
+    (file) => `:(exclude)${path.resolve(file)}`
+  );
+  const status = await git(
+    argv.dir || '.',
+    'status',
+    '--porcelain',
+    '--untracked-files=all',
+    '--',
+    ':/',
+    ...outputs
+  );
+  if (status.trim()) {
+    throw new KibobError(
+      'UNCOMMITTED',
+      `Commit or stash these changes before tagging ${argv.tag}:\n${status}`
+    );
+  }
+}
+
+/* Tag the commit a bundle was made from, noting its objects and sha256,
+ * after committing the section just added to --changelog, if any.
+ */
+async function tagRelease(argv, manifest) {
+  const dir = argv.dir || '.';
+  if (argv.changelog) {
+    const changelog = path.resolve(argv.changelog);
+    if ((await git(dir, 'status', '--porcelain', '--', changelog)).trim()) {
+      await git(dir, 'add', '--', changelog);
+      const message = `Changelog for ${argv.tag}`;
+      await git(dir, 'commit', '-m', message, '--', changelog);
+      logger.info(`Committed ${argv.changelog}`);
+    }
+  }
+  const message =
+    `${argv.tag}: ${manifest.count} objects in ${manifest.file}\n\n` +
+    `sha256 ${manifest.sha256}`;
+  await git(dir, 'tag', '-a', argv.tag, '-m', message);
+  logger.info(`Tagged ${argv.tag}`);
+}
+
+// The manifest written next to a bundle, e.g. export.manifest.json
+function manifestFile(file) {
+  const base = path.basename(file, path.extname(file));
+  return path.join(path.dirname(file), `${base}.manifest.json`);
+}
+
+async function fileHash(file) {
+  const hash = crypto.createHash('sha256');
+  for await (const chunk of createReadStream(file)) hash.update(chunk);
+  return hash.digest('hex');
+}
+
+/* Describe a bundle in its manifest: the number of objects of each of the
+ * types written and the sha256 of the file, so that whoever installs it can
+ * tell it is whole.
+ */
+async function writeManifest(file, types) {
+  const manifest = {
+    file: path.basename(file),
+    count: types.length,
+    types: transforms.normalizeObject(R.countBy(R.identity, types)),
+    sha256: await fileHash(file),
+  };
+  const data = JSON.stringify(manifest, null, 2) + '\n';
+  await state.writeAtomic(manifestFile(file), data);
+  return manifest;
+}
+
+/* Check a bundle against its manifest, if it has one, and throw when the
+ * file was changed or cut short after it was bundled.
+ */
+async function verifyManifest(file) {
+  let manifest;
+  try {
+    manifest = JSON.parse(await fs.readFile(manifestFile(file), 'utf8'));
+  } catch (err) {
+    if (err.code === 'ENOENT') return;
+    throw err;
+  }
+  if ((await fileHash(file)) === manifest.sha256) return;
+
+  // a truncated bundle ends in a partial line, which is not counted
+  let count = 0;
+  for await (const line of readLines(file)) {
+    try {
+      if (JSON.parse(line).type) count++;
+    } catch (err) {
+      break;
+    }
+  }
+  throw new KibobError(
+    'INVALID_BUNDLE',
+    `${file} does not match ${manifestFile(file)}: ` +
+      `it has ${count} of ${manifest.count} objects and a different sha256`
+  );
+}
+
+/* Split an export into a directory per object type, without the file name
+ * tracking and git checks of unbundle. Meant for reviewing an export once,
+ * like one sent in by a customer.
+ */
+async function splitObjects(argv) {
+  const dir = argv.dir || path.basename(argv.file, path.extname(argv.file));
+  const names = {};
+  let count = 0;
+  try {
+    for await (const obj of readNdjson(argv.file)) {
+      if (!obj.type) continue;
+      // the type names a directory, and customer files are not trusted
+      if (safeSegment(obj.type) !== obj.type) {
+        logger.error(`Skipping ${obj.type}/${obj.id}: unsafe type`);
+        continue;
+      }
+      await fs.mkdir(`${dir}/${obj.type}`, { recursive: true });
+      names[obj.type] = names[obj.type] || {};
+      await writeObjectFile(argv, `${dir}/${obj.type}`, obj, names[obj.type]);
+      count++;
+    }
+    logger.info(`Split ${count} objects into ${dir}`);
+    return { dir, count };
+  } catch (err) {
+    commandError(err);
+  }
+}
+
+// Reverse of splitObjects, bundle every type directory into one file
+async function joinObjects(argv) {
+  const file = argv.file || `${path.basename(path.resolve(argv.dir))}.ndjson`;
+  async function* objects() {
+    for (const type of (await fs.readdir(argv.dir)).sort()) {
+      const dir = `${argv.dir}/${type}`;
+      if (!(await fs.stat(dir)).isDirectory()) continue;
+      for (const obj of await readObjects(dir)) yield R.omit(['file'], obj);
+    }
+  }
+
+  try {
+    const count = await writeNdjson(file, objects());
+    logger.info(`Joined ${count} objects into ${file}`);
+    return { file, count };
+  } catch (err) {
+    commandError(err);
+  }
+}
+
+// Read every .json file in a directory into an array of saved objects
+async function readObjects(dir) {
+  const objects = [];
+  for (const name of await jsonFiles(dir)) {
+    objects.push({ ...(await readObjectFile(dir, name)), file: name });
+  }
+  return objects;
+}
+
+// The .json files in a directory, less those matched by its .kibobignore
+async function jsonFiles(dir) {
+  const ignored = await ignore.readIgnore(dir);
+  return (await fs.readdir(dir)).filter(
+    (name) => path.extname(name) === '.json' && !ignored(name)
+  );
+}
+
+/* Whether two copies of an object have the same content, ignoring the JSON
+ * encoding, key order and reference order that unbundle changes, and unless
+ * exact, the layout of dashboard panels.
+ */
+function sameObject(local, remote, exact = false) {
+  return R.equals(objectContent(local, exact), objectContent(remote, exact));
+}
+
+// A saved object from Kibana with its secrets scrubbed, like a pulled file
+const scrubbed = (argv, obj) =>
+  transforms.scrubSecrets(obj, transformsFor(argv, obj.type).secrets || []);
+
+const exactContent = R.pipe(
+  R.pick(['type', 'attributes', 'references']),
+  transforms.expandJsonStrings,
+  transforms.normalizeObject
+);
+
+const objectContent = (obj, exact) =>
+  exact ? exactContent(obj) : transforms.ignoreLayout(exactContent(obj));
+
+const objectKey = (obj) => `${obj.type}/${obj.id}`;
+
+/* Hash of an object's content, compared like sameObject with exact, so a
+ * panel moved in Kibana counts as drift from the lockfile.
+ */
+function objectHash(obj) {
+  const json = JSON.stringify(objectContent(obj, true));
+  return crypto.createHash('sha256').update(json).digest('hex');
+}
+
+// The lockfile keeps a section per Kibana host and space
+function lockTarget(argv) {
+  return new URL(argv.url).host + spacePath(argv, '');
+}
+
+async function readLock(argv) {
+  try {
+    return JSON.parse(await fs.readFile(argv.lock, 'utf8'));
+  } catch (err) {
+    if (err.code === 'ENOENT') return {};
+    throw err;
+  }
+}
+
+/* Read, change and write back a file shared by the runs of --all-remotes,
+ * like the lockfile, holding the state lock while doing so. Other commands
+ * hold it for as long as they run already.
+ */
+function lockedWrite(argv, fn) {
+  if (!argv.lockPerWrite) return fn();
+  return state.withLock(argv._[0], argv.lockTimeout, fn);
+}
+
+// Record the hashes of objects as Kibana has them after an export or import
+async function updateLock(argv, objects) {
+  if (!argv.lock) return;
+  await lockedWrite(argv, async () => {
+    const lock = await readLock(argv);
+    const target = lockTarget(argv);
+    lock[target] = lock[target] || {};
+    for (const obj of objects) lock[target][objectKey(obj)] = objectHash(obj);
+    const data = JSON.stringify(transforms.normalizeObject(lock), null, 2);
+    await state.writeAtomic(argv.lock, data + '\n');
+  });
+}
+
+// Stop tracking deleted objects in the lockfile
+async function forgetLock(argv, keys) {
+  if (!argv.lock || keys.length === 0) return;
+  await lockedWrite(argv, async () => {
+    const lock = await readLock(argv);
+    const target = lockTarget(argv);
+    lock[target] = R.omit(keys, lock[target] || {});
+    const data = JSON.stringify(transforms.normalizeObject(lock), null, 2);
+    await state.writeAtomic(argv.lock, data + '\n');
+  });
+}
+
+/* The tracked objects that changed in Kibana since kibob last exported or
+ * imported them, usually hotfixes made in the UI that an import would
+ * silently overwrite, and those deleted from Kibana since.
+ */
+async function driftedObjects(argv, objects, hashes) {
+  const tracked = objects.filter((obj) => hashes[objectKey(obj)]);
+  if (tracked.length === 0) return { drifted: [], missing: [] };
+  const current = await bulkGetObjects(argv, tracked);
+  return {
+    drifted: current.filter(
+      (obj) => !obj.error && objectHash(obj) !== hashes[objectKey(obj)]
+    ),
+    missing: current.filter((obj) => obj.error),
+  };
+}
+
+/* Import, refusing when objects drifted from the lockfile unless --force,
+ * then record what Kibana holds for the imported objects in the lockfile.
+ * Objects that match the lockfile are skipped unless --all is given, or
+ * they were deleted from Kibana.
+ */
+async function importLocked(argv) {
+  try {
+    await verifyManifest(argv.file);
+  } catch (err) {
+    if (libraryMode) throw err;
+    fail(exitCodes.invalid);
+    logger.error(err.message);
+    return;
+  }
+  if (argv.shortUrls) {
+    argv.slugs = await importShortUrls(argv);
+    if (!argv.slugs) return;
+  }
+  if (argv.pruneRemote && !argv.lock) {
+    commandError(
+      new Error('--prune-remote needs the lockfile to know what to delete')
+    );
+    return;
+  }
+  if (!argv.lock) return importBatches(argv, streamContent(argv));
+  try {
+    return await importTracked(argv, () => streamContent(argv));
+  } catch (err) {
+    commandError(err);
+  }
+}
+
+/* Import the objects that changed since the lockfile recorded them, unless
+ * one of them has changed in Kibana as well, and record each batch as Kibana
+ * has it after. content returns the objects; it is read once to compare them
+ * with Kibana a batch at a time and once more to import them, so that only
+ * their keys are held in memory.
+ */
+async function importTracked(argv, content) {
+  const hashes = (await readLock(argv))[lockTarget(argv)] || {};
+  const keys = new Set();
+  const drifted = [];
+  const missing = new Set();
+  for await (const batch of batchesOf(argv.batchSize, content())) {
+    for (const obj of batch) keys.add(objectKey(obj));
+    const remote = await driftedObjects(argv, batch, hashes);
+    drifted.push(...remote.drifted.map(objectKey));
+    for (const obj of remote.missing) missing.add(objectKey(obj));
+  }
+  if (drifted.length > 0 && !argv.force) {
+    if (libraryMode) {
+      throw new KibobError(
+        'DRIFTED',
+        `${drifted.length} objects changed in Kibana since the lockfile`,
+        { drifted }
+      );
+    }
+    for (const key of drifted) {
+      logger.error(
+        `${key} changed in Kibana since it was last exported or ` +
+          'imported, export it first or use --force to overwrite it'
+      );
+    }
+    fail();
+    return;
+  }
+
+  let skipped = 0;
+  const changed = async function* () {
+    for await (const obj of content()) {
+      const key = objectKey(obj);
+      const stale = drifted.includes(key) || missing.has(key);
+      if (argv.all || stale || objectHash(obj) !== hashes[key]) yield obj;
+      else skipped++;
+    }
+  };
+  const result = await importBatches(argv, changed(), async (imported) => {
+    const current = await bulkGetObjects(argv, imported);
+    await updateLock(argv, current.filter((obj) => !obj.error));
+  });
+  if (skipped > 0) {
+    logger.info(
+      `Skipped ${skipped} objects unchanged since the last import, ` +
+        'use --all to import them anyway'
+    );
+  }
+  if (result && argv.pruneRemote && result.success) {
+    result.pruned = await pruneRemote(argv, keys, hashes);
+  }
+  return result && { ...result, skipped };
+}
+
+/* Delete the objects the lockfile tracks for this Kibana that are no longer
+ * in the import file, whose keys are in kept, after asking unless --yes.
+ * Objects kibob never exported or imported are not in the lockfile, so they
+ * are left alone.
+ */
+async function pruneRemote(argv, kept, hashes) {
+  const removed = Object.keys(hashes)
+    .filter((key) => !kept.has(key))
+    .map((key) => {
+      const [type, ...id] = key.split('/');
+      return { type, id: id.join('/') };
+    })
+    .filter((obj) => hasType(argv, obj));
+  if (removed.length === 0) return [];
+
+  for (const obj of removed) logger.info(`Pruning ${objectKey(obj)}`);
+  const question = `Delete ${removed.length} objects from Kibana? [y/N] `;
+  if (!argv.yes && !(await confirm(question))) {
+    logger.warn('Nothing pruned, use --yes to prune without asking');
+    return [];
+  }
+
+  const deleted = [];
+  for (const obj of removed) {
+    if (await deleteObject({ ...argv, ...obj })) deleted.push(objectKey(obj));
+  }
+  await forgetLock(argv, deleted);
+  return deleted;
+}
+
+// Ask a yes or no question on stderr, no when there is no terminal to ask on
+async function confirm(question) {
+  if (!process.stdin.isTTY) return false;
+  const prompt = readline.createInterface({
+    input: process.stdin,
+    output: process.stderr,
+  });
+  const answer = await new Promise((resolve) =>
+    prompt.question(question, resolve)
+  );
+  prompt.close();
+  return /^y(es)?$/i.test(answer.trim());
+}
+
+// Compare local .json files to the saved objects in Kibana, like `git status`
+async function objectStatus(argv) {
+  try {
+    const local = (await readObjects(argv.dir)).filter((obj) =>
+      hasType(argv, obj)
+    );
+    // findObjects already failed the command if Kibana could not be read
+    const remote = await findObjects(argv);
+    if (!remote) return;
+    const key = (obj) => `${obj.type}/${obj.id}`;
+    const remoteByKey = R.indexBy(key, remote);
+    const localKeys = new Set(local.map(key));
+    const describe = (obj) => ({
+      type: obj.type,
+      id: obj.id,
+      title: objectTitle(obj),
+    });
+
+    const status = { modified: [], missingRemotely: [], missingLocally: [] };
+    for (const obj of local) {
+      const other = remoteByKey[key(obj)];
+      if (!other) {
+        status.missingRemotely.push({ ...describe(obj), file: obj.file });
+      } else if (!sameObject(obj, scrubbed(argv, other), argv.exact)) {
+        status.modified.push({ ...describe(obj), file: obj.file });
+      }
+    }
+    for (const obj of remote) {
+      if (!localKeys.has(key(obj))) status.missingLocally.push(describe(obj));
+    }
+
+    return status;
+  } catch (err) {
+    commandError(err);
+  }
+}
+
+// Print the objects that differ, one per line like `git status`
+function printStatus(status) {
+  const labels = {
+    modified: 'modified',
+    missingRemotely: 'missing remotely',
+    missingLocally: 'missing locally',
+  };
+  let clean = true;
+  for (const [field, label] of Object.entries(labels)) {
+    for (const obj of status[field]) {
+      console.log(`${label}:\t${obj.type}/${obj.id} "${obj.title}"`);
+      clean = false;
+    }
+  }
+  if (clean) logger.info('Local objects match Kibana');
+}
+
+/* A read-only compliance report of the objects in Kibana: whether each is
+ * managed, who last updated it when Kibana records that, and whether it
+ * matches the copy in the directory.
+ */
+async function auditObjects(argv) {
+  try {
+    const local = (await readObjects(argv.dir)).filter((obj) =>
+      hasType(argv, obj)
+    );
+    const remote = await findObjects(argv);
+    if (!remote) return;
+    const localByKey = R.indexBy(objectKey, local);
+    const remoteKeys = new Set(remote.map(objectKey));
+    const describe = (obj) => ({
+      type: obj.type,
+      id: obj.id,
+      title: objectTitle(obj),
+      managed: obj.managed === true,
+      updatedBy: obj.updated_by || null,
+      updatedAt: obj.updated_at || null,
+    });
+
+    const objects = remote.map((obj) => {
+      const copy = localByKey[objectKey(obj)];
+      const state = !copy
+        ? 'not in repo'
+        : sameObject(copy, scrubbed(argv, obj), argv.exact)
+        ? 'matches'
+        : 'modified';
+      return { ...describe(obj), state };
+    });
+    for (const obj of local) {
+      if (!remoteKeys.has(objectKey(obj))) {
+        objects.push({
+          ...describe(obj),
+          managed: null,
+          state: 'missing remotely',
+        });
+      }
+    }
+
+    const count = (state) => objects.filter((o) => o.state === state).length;
+    return {
+      target: lockTarget(argv),
+      dir: argv.dir,
+      time: new Date().toISOString(),
+      summary: {
+        total: objects.length,
+        matches: count('matches'),
+        modified: count('modified'),
+        notInRepo: count('not in repo'),
+        missingRemotely: count('missing remotely'),
+        managed: objects.filter((o) => o.managed).length,
+      },
+      objects,
+    };
+  } catch (err) {
+    commandError(err);
+  }
+}
+
+// Print an audit report as a Markdown document
+function printAudit(audit) {
+  const cell = (value) =>
+    value === null ? '-' : String(value).replace(/\|/g, '\\|');
+  const { summary } = audit;
+  const lines = [
+    `# Kibana audit of ${audit.target}`,
+    '',
+    `Compared with \`${audit.dir}\` at ${audit.time}.`,
+    '',
+    `- ${summary.total} objects, ${summary.managed} managed`,
+    `- ${summary.matches} match the repository`,
+    `- ${summary.modified} modified in Kibana`,
+    `- ${summary.notInRepo} not in the repository`,
+    `- ${summary.missingRemotely} missing from Kibana`,
+    '',
+    '| Type | ID | Title | Managed | Updated by | Updated at | State |',
+    '| --- | --- | --- | --- | --- | --- | --- |',
+    ...audit.objects.map((obj) => {
+      const columns = [obj.type, obj.id, obj.title, obj.managed];
+      columns.push(obj.updatedBy, obj.updatedAt, obj.state);
+      return `| ${columns.map(cell).join(' | ')} |`;
+    }),
+  ];
+  console.log(lines.join('\n'));
+}
+
+// Run a git command from inside a directory and return its stdout
+async function git(dir, ...args) {
+  const { stdout } = await promisify(execFile)('git', args, {
+    cwd: dir,
+    maxBuffer: 256 * 1024 * 1024,
+  });
+  return stdout;
+}
+
+/* The .json files of a directory at a git ref, or on disk without one,
+ * as a function to read an object by file name. Markdown extracted into .md
+ * files is read back in from the same ref, so edits to it show up too.
+ */
+async function filesAt(dir, ref) {
+  if (!ref) {
+    const read = async (name) =>
+      markdown.inlineMarkdown(
+        JSON.parse(await fs.readFile(`${dir}/${name}`, 'binary')),
+        dir
+      );
+    return { names: await jsonFiles(dir), read };
+  }
+  const ignored = await ignore.readIgnore(dir);
+  const names = (await git(dir, 'ls-tree', '--name-only', ref, '.'))
+    .split('\n')
+    .filter((name) => path.extname(name) === '.json' && !ignored(name));
+  const show = (file) => git(dir, 'show', `${ref}:./${file}`);
+  const read = async (name) =>
+    markdown.inlineMarkdown(JSON.parse(await show(name)), dir, show);
+  return { names, read };
+}
+
+/* Expand and sort an object, so how it was written does not show as a
+ * change, and unless exact, leave out the layout of dashboard panels.
+ */
+function normalizeForDiff(obj, exact) {
+  const normalized = transforms.normalizeObject(
+    transforms.expandJsonStrings(obj)
+  );
+  return exact ? normalized : transforms.ignoreLayout(normalized);
+}
+
+/* Structurally diff each .json file between two git refs, by default HEAD
+ * and the disk, e.g. between release tags for release notes.
+ */
+async function diffDirectory(argv) {
+  const changed = [];
+  try {
+    const from = await filesAt(argv.dir, argv.from || 'HEAD');
+    const to = await filesAt(argv.dir, argv.to);
+
+    for (const name of R.union(from.names, to.names).sort()) {
+      const before = from.names.includes(name)
+        ? normalizeForDiff(await from.read(name), argv.exact)
+        : null;
+      const after = to.names.includes(name)
+        ? normalizeForDiff(await to.read(name), argv.exact)
+        : null;
+      const obj = after || before;
+      const entry = {
+        file: name,
+        type: obj.type,
+        id: obj.id,
+        title: objectTitle(obj),
+      };
+
+      if (!before) {
+        changed.push({ ...entry, change: 'added' });
+      } else if (!after) {
+        changed.push({ ...entry, change: 'deleted' });
+      } else {
+        const changes = diff.diffObjects(before, after);
+        if (R.all(R.isEmpty, R.values(changes))) continue;
+        changed.push({ ...entry, change: 'modified', ...changes });
+      }
+    }
+    return changed;
+  } catch (err) {
+    commandError(err);
+  }
+}
+
+// Print each changed file followed by an indented summary of its changes
+function printDiff(changed) {
+  for (const obj of changed) {
+    console.log(`${obj.change}:\t${obj.file} (${obj.type}/${obj.id})`);
+    if (obj.change !== 'modified') continue;
+    for (const field of obj.fields) {
+      console.log(`  ${field.change} ${field.path}`);
+    }
+    for (const panel of obj.panelsAdded) console.log(`  + panel ${panel}`);
+    for (const panel of obj.panelsRemoved) console.log(`  - panel ${panel}`);
+    for (const ref of obj.referencesAdded) console.log(`  + reference ${ref}`);
+    for (const ref of obj.referencesRemoved) {
+      console.log(`  - reference ${ref}`);
+    }
+  }
+}
+
+/* Delete the local .json file(s) holding a saved object, with their assets
+ * and their entry in .kibob-files.
+ */
+async function removeObject(argv) {
+  const removed = [];
+  try {
+    const matches = (await readObjects(argv.dir)).filter(
+      (obj) => obj.type === argv.type && obj.id === argv.id
+    );
+    if (matches.length === 0) {
+      logger.warn(`${argv.type}/${argv.id} not found in ${argv.dir}`);
+      return removed;
+    }
+    for (const obj of matches) {
+      await removeObjectFile(argv.dir, obj.file);
+      logger.info(`Removed ${argv.dir}/${obj.file}`);
+      removed.push(obj.file);
+    }
+    const names = await readFileNames(argv.dir);
+    delete names[`${argv.type}/${argv.id}`];
+    await saveFileNames(argv.dir, names);
+  } catch (err) {
+    commandError(err);
+  }
+  return removed;
+}
+
+/* Give an object a new --title and/or --new-id. Its file is renamed to
+ * match the file name template, and with a new id every other object's
+ * references to it are rewritten, like --remap does.
+ */
+async function renameObject(argv) {
+  try {
+    const names = await readFileNames(argv.dir);
+    const objects = await readObjects(argv.dir);
+    const key = `${argv.type}/${argv.id}`;
+    const target = objects.find((obj) => objectKey(obj) === key);
+    if (!target) {
+      throw new KibobError('NOT_FOUND', `${key} is not in ${argv.dir}`);
+    }
+
+    const ids = argv.newId ? { [argv.id]: argv.newId } : {};
+    const rewritten = [];
+    for (const { file, ...obj } of objects) {
+      let renamed = transforms.remapIds(obj, ids);
+      if (objectKey(obj) === key) {
+        if (argv.title) {
+          const field = 'title' in obj.attributes ? 'title' : 'name';
+          renamed = R.assocPath(['attributes', field], argv.title, renamed);
+        }
+        delete names[key];
+        const name = await writeObjectFile(argv, argv.dir, renamed, names);
+        if (name !== file) await removeObjectFile(argv.dir, file);
+        logger.info(`Renamed ${argv.dir}/${file} to ${name}`);
+        rewritten.push(name);
+      } else if (!R.equals(renamed, obj)) {
+        names[objectKey(obj)] = file;
+        await writeObjectFile(argv, argv.dir, renamed, names);
+        logger.info(`Updated references in ${argv.dir}/${file}`);
+        rewritten.push(file);
+      }
+    }
+    await saveFileNames(argv.dir, names);
+    return { rewritten };
+  } catch (err) {
+    commandError(err);
+  }
+}
+
+// Delete an object's file along with its extracted assets, if any
+async function removeObjectFile(dir, file) {
+  await fs.unlink(`${dir}/${file}`);
+  const assetDir = `${dir}/${path.basename(file, '.json')}.assets`;
+  await fs.rm(assetDir, { recursive: true, force: true });
+}
+
+// Delete a saved object from Kibana
+// https://www.elastic.co/guide/en/kibana/current/saved-objects-api-delete.html
+async function deleteObject(argv) {
+  const url = new URL(argv.url);
+  url.pathname = spacePath(
+    argv,
+    `/api/saved_objects/${argv.type}/${argv.id}`
+  );
+
+  const options = {
+    method: 'DELETE',
+    headers: kibanaHeaders(argv),
+  };
+
+  try {
+    const res = await withRetry(argv, () => kibanaFetch(argv, url, options));
+    if (res.status === 200) {
+      logger.info(
+        `${res.status} ${res.statusText} Deleted ${argv.type}/${argv.id}`
+      );
+      return true;
+    } else {
+      const body = JSON.stringify(await res.json(), null, 2);
+      logger.error(`${res.status} ${res.statusText} Error: ${body}`);
+    }
+  } catch (FetchError) {
+    logger.error(`${FetchError.message}`);
+  }
+  return false;
+}
+
+/* The scratch space of `dev push`, dev-<user> for --user or the OS user,
+ * with anything a space id cannot hold turned into dashes.
+ */
+function devSpace(argv) {
+  const user = argv.user || os.userInfo().username;
+  return `dev-${user.toLowerCase().replace(/[^a-z0-9_-]+/g, '-')}`;
+}
+
+// Where each type of object opens in Kibana
+const appPaths = {
+  dashboard: (id) => `/app/dashboards#/view/${id}`,
+  visualization: (id) => `/app/visualize#/edit/${id}`,
+  lens: (id) => `/app/lens#/edit/${id}`,
+  search: (id) => `/app/discover#/view/${id}`,
+  map: (id) => `/app/maps/map/${id}`,
+  'canvas-workpad': (id) => `/app/canvas#/workpad/${id}`,
+  'index-pattern': (id) => `/app/management/kibana/dataViews/dataView/${id}`,
+};
+
+// A link to open an object in Kibana, without the credentials of the URL
+function deepLink(argv, obj) {
+  const app = appPaths[obj.type];
+  if (!app) return null;
+  return new URL(argv.url).origin + spacePath(argv, app(obj.id));
+}
+
+// Create the --space unless Kibana has it already
+async function ensureSpace(argv, name) {
+  const found = await getJson(argv, `/api/spaces/space/${argv.space}`);
+  if (found.status === 200) return false;
+  if (found.status !== 404) {
+    throw apiError(found.status, found.statusText, found.body);
+  }
+  const url = new URL('/api/spaces/space', argv.url);
+  const options = {
+    method: 'POST',
+    headers: { ...kibanaHeaders(argv), 'Content-Type': 'application/json' },
+    body: JSON.stringify({ id: argv.space, name }),
+  };
+  const res = await withRetry(argv, () => kibanaFetch(argv, url, options));
+  if (!res.ok) {
+    const body = await res.json().catch(() => ({}));
+    throw apiError(res.status, res.statusText, body);
+  }
+  logger.info(`Created space ${argv.space}`);
+  return true;
+}
+
+// Mark an object's title, or a tag's name, with the dev space it is in
+function stampTitle(obj, space) {
+  const attributes = obj.attributes || {};
+  const field = R.has('title', attributes) ? 'title' : 'name';
+  const title = attributes[field];
+  const stamp = ` [${space}]`;
+  if (typeof title !== 'string' || title.endsWith(stamp)) return obj;
+  return R.assocPath(['attributes', field], title + stamp, obj);
+}
+
+/* Import the objects in a directory into a space of the user's own,
+ * dev-<user>, created if Kibana does not have it. Ids get a -dev-<user>
+ * suffix, references included, so they never clash with the copies shared
+ * with other spaces, and titles are stamped with the space. The lockfile is
+ * left alone, since this is not the target the objects are tracked for. Each
+ * object pushed is noted in .kibob/dev/<space>.json with a link to it.
+ */
+async function devPush(argv) {
+  try {
+    const space = devSpace(argv);
+    const target = { ...argv, space, overwrite: true };
+    const suffix = `-${space}`;
+    const transform = await pushTransform(argv);
+    const objects = [];
+    for (const name of await jsonFiles(argv.dir)) {
+      const obj = await readObjectFile(argv.dir, name);
+      if (obj.type && hasType(argv, obj)) objects.push(transform(obj));
+    }
+    if (objects.length === 0) {
+      logger.warn(`No objects in ${argv.dir} to push`);
+      return [];
+    }
+
+    const ids = R.fromPairs(objects.map((obj) => [obj.id, obj.id + suffix]));
+    const stamped = objects.map((obj) =>
+      stampTitle(transforms.remapIds(obj, ids), space)
+    );
+    await ensureSpace(target, space);
+    const result = await importBatches(target, stamped);
+    printImport(result);
+
+    const failed = new Set(result.errors.map(objectKey));
+    const pushed = R.zip(objects, stamped)
+      .filter(([, copy]) => !failed.has(objectKey(copy)))
+      .map(([obj, copy]) => ({
+        type: obj.type,
+        id: obj.id,
+        devId: copy.id,
+        title: objectTitle(copy),
+        link: deepLink(target, copy),
+      }));
+    const previous = (await state.readDevMapping(space)) || { objects: [] };
+    await state.writeDevMapping(space, {
+      url: new URL(argv.url).origin,
+      space,
+      time: new Date().toISOString(),
+      objects: R.uniqBy(objectKey, [...pushed, ...previous.objects]),
+    });
+    return pushed;
+  } catch (err) {
+    commandError(err);
+  }
+}
+
+/* Delete the dev-<user> space from Kibana, or with --objects-only just the
+ * objects in it with the -dev-<user> suffix of `dev push`, leaving anything
+ * made there by hand. Either way the space's .kibob/dev mapping is removed.
+ */
+async function devClean(argv) {
+  try {
+    const space = devSpace(argv);
+    const target = { ...argv, space };
+    const cleaned = { space, spaceDeleted: false, deleted: [] };
+    const found = await getJson(argv, `/api/spaces/space/${space}`);
+    if (found.status === 404) {
+      logger.warn(`Kibana has no ${space} space`);
+    } else if (found.status !== 200) {
+      throw apiError(found.status, found.statusText, found.body);
+    } else if (argv.objectsOnly) {
+      const suffix = `-${space}`;
+      const remote = await findObjects({ ...target, search: undefined });
+      if (!remote) return;
+      const pushed = remote.filter((obj) => obj.id.endsWith(suffix));
+      const question = `Delete ${pushed.length} objects from ${space}? [y/N] `;
+      if (pushed.length > 0 && !argv.yes && !(await confirm(question))) {
+        logger.warn('Nothing deleted, use --yes to delete without asking');
+        return cleaned;
+      }
+      for (const obj of pushed) {
+        const one = { ...target, type: obj.type, id: obj.id };
+        if (await deleteObject(one)) cleaned.deleted.push(objectKey(obj));
+      }
+    } else {
+      const question = `Delete the ${space} space and all in it? [y/N] `;
+      if (!argv.yes && !(await confirm(question))) {
+        logger.warn('Nothing deleted, use --yes to delete without asking');
+        return cleaned;
+      }
+      const url = new URL(`/api/spaces/space/${space}`, argv.url);
+      const options = { method: 'DELETE', headers: kibanaHeaders(argv) };
+      const res = await withRetry(argv, () => kibanaFetch(argv, url, options));
+      if (!res.ok) {
+        const body = await res.json().catch(() => ({}));
+        throw apiError(res.status, res.statusText, body);
+      }
+      logger.info(`Deleted space ${space}`);
+      cleaned.spaceDeleted = true;
+    }
+    if (await state.removeDevMapping(space)) {
+      logger.info(`Removed ${state.devMappingFile(space)}`);
+    }
+    return cleaned;
+  } catch (err) {
+    commandError(err);
+  }
+}
+
+// Print the title of each object pushed and the link to open it with
+function printDevPush(pushed) {
+  printTable(
+    ['type', 'title', 'link'],
+    pushed.map((obj) => ({ ...obj, link: obj.link || '-' }))
+  );
+}
+
+// Validate a directory of .json files, exiting non-zero if problems are found
+async function validateDirectory(argv) {
+  try {
+    const files = [];
+    for (const file of await jsonFiles(argv.dir)) {
+      const text = await fs.readFile(`${argv.dir}/${file}`, 'utf8');
+      files.push({ file, text });
+    }
+
+    const orphans = orphanedFiles(files, await readFileNames(argv.dir));
+    const problems = [];
+    for (const { file, key, current } of orphans) {
+      if (argv.prune) {
+        await removeObjectFile(argv.dir, file);
+        logger.info(`Deleted ${argv.dir}/${file}, ${key} is in ${current}`);
+      } else {
+        problems.push({ file, problem: `orphaned, ${key} is in ${current}` });
+      }
+    }
+    const pruned = argv.prune ? orphans.map(R.prop('file')) : [];
+    problems.push(
+      ...validateFiles(files.filter(({ file }) => !pruned.includes(file)))
+    );
+    if (problems.length > 0) fail(exitCodes.invalid);
+    for (const { file, problem } of problems) {
+      logger.error(`${argv.dir}/${file}: ${problem}`);
+    }
+    if (problems.length === 0) {
+      const count = files.length - pruned.length;
+      logger.info(`${count} objects in ${argv.dir} are valid`);
+    }
+    return { valid: problems.length === 0, problems, pruned };
+  } catch (err) {
+    commandError(err);
+  }
+}
+
+// Export objects from one remote and import them into another, in memory
+async function cloneObjects(argv) {
+  try {
+    // without --map-space both ends use --space, by each remote's name for it
+    const [fromSpace, toSpace] = (argv.mapSpace || '').split(':');
+    const endpoint = (name, space) => {
+      const remote = remoteConfig(name);
+      return { ...argv, ...remote, space: remote.spaces[space] || space };
+    };
+    const source = endpoint(argv.from, fromSpace || argv.space);
+    const target = endpoint(argv.to, toSpace || argv.space);
+
+    const objects = await findObjects(source);
+    if (!objects) return;
+    return importBatches(
+      { ...target, file: `${argv.from}.ndjson` },
+      objects.map(withoutVersion)
+    );
+  } catch (err) {
+    commandError(err);
+  }
+}
+
+/* Export every object of --types to a file named after the current time,
+ * e.g. snapshots/2024-05-01T12-00-00-000Z.ndjson, for rollback to restore.
+ */
+async function snapshotObjects(argv) {
+  try {
+    // findObjects already failed the command if Kibana could not be read
+    const objects = await findObjects(argv);
+    if (!objects) return;
+    await fs.mkdir(argv.dir, { recursive: true });
+    const time = new Date().toISOString().replace(/[:.]/g, '-');
+    const file = `${argv.dir}/${time}.ndjson`;
+    const transform = R.pipe(withoutVersion, transforms.normalizeObject);
+    const sorted = graph.sortByReferences(objects.map(transform));
+    const count = await writeNdjson(file, sorted);
+    logger.info(`Saved a snapshot of ${count} objects to ${file}`);
+    return { file, count };
+  } catch (err) {
+    commandError(err);
+  }
+}
+
+/* Import a snapshot over the objects in Kibana. With --prune, also delete
+ * the objects of the snapshot's types that are not in it.
+ */
+async function rollbackObjects(argv) {
+  try {
+    let file = argv.snapshot;
+    if (!file) {
+      const snapshots = (await fs.readdir(argv.dir))
+        .filter((name) => path.extname(name) === '.ndjson')
+        .sort();
+      if (snapshots.length === 0) {
+        throw new KibobError('NOT_FOUND', `No snapshots in ${argv.dir}`);
+      }
+      file = `${argv.dir}/${snapshots[snapshots.length - 1]}`;
+    }
+    logger.info(`Rolling back to ${file}`);
+
+    const snapshot = [];
+    for await (const obj of readNdjson(file)) {
+      if (obj.type) snapshot.push(obj);
+    }
+    const result = await importBatches(
+      { ...argv, file, overwrite: true },
+      snapshot
+    );
+    if (!argv.prune || !result || result.errors.length > 0) return result;
+
+    const kept = new Set();
+    const types = new Set();
+    for await (const obj of readNdjson(file)) {
+      kept.add(objectKey(obj));
+      types.add(obj.type);
+    }
+    const current = await findObjects({ ...argv, types: [...types] });
+    if (!current) return result;
+    const removed = current.filter((obj) => !kept.has(objectKey(obj)));
+    if (removed.length === 0) return { ...result, pruned: [] };
+
+    for (const obj of removed) logger.info(`Pruning ${objectKey(obj)}`);
+    const question = `Delete ${removed.length} objects from Kibana? [y/N] `;
+    if (!argv.yes && !(await confirm(question))) {
+      logger.warn('Nothing pruned, use --yes to prune without asking');
+      return { ...result, pruned: [] };
+    }
+    const pruned = [];
+    for (const obj of removed) {
+      const target = { ...argv, type: obj.type, id: obj.id };
+      if (await deleteObject(target)) pruned.push(objectKey(obj));
+    }
+    return { ...result, pruned };
+  } catch (err) {
+    commandError(err);
+  }
+}
+
+/* Import .json files into Kibana as they are saved, batching changes that
+ * arrive within --debounce of each other. With --poll, also write objects
+ * changed in Kibana back to the directory, ignoring the file events that
+ * those writes cause. Resolves to a handle whose close() stops watching.
+ */
+async function watchDirectory(argv) {
+  const quietPeriod = 2000;
+  const pending = new Set();
+  const pulled = new Map();
+  let timer;
+
+  const push = async () => {
+    const objects = [];
+    for (const name of pending) {
+      try {
+        objects.push(transform(await readObjectFile(argv.dir, name)));
+      } catch (err) {
+        logger.warn(`Skipping ${name}: ${err.message}`);
+      }
+    }
+    const names = [...pending];
+    pending.clear();
+    if (objects.length === 0) return;
+
+    // like import, objects changed in Kibana since the lockfile are not
+    // overwritten, and those imported are recorded in it
+    const options = { ...argv, overwrite: true, file: argv.dir };
+    const sorted = graph.sortByReferences(objects);
+    const send = () =>
+      argv.lock
+        ? importTracked(options, () => sorted)
+        : importBatches(options, sorted);
+    try {
+      const result = await state.withLock('watch', argv.lockTimeout, send);
+      if (result) printImport(result);
+    } catch (err) {
+      if (err.code !== 'LOCKED') {
+        logger.error(err.message);
+        return;
+      }
+      // try the same files again once the other kibob is done
+      logger.warn(err.message);
+      for (const name of names) pending.add(name);
+      clearTimeout(timer);
+      timer = setTimeout(push, argv.debounce);
+    }
+  };
+
+  const pull = async () => {
+    try {
+      const remote = await findObjects(argv);
+      if (!remote) return;
+      const key = (obj) => `${obj.type}/${obj.id}`;
+      const written = [];
+      const synced = [];
+      await state.withLock('watch', argv.lockTimeout, async () => {
+        const local = R.indexBy(key, await readObjects(argv.dir));
+        const lock = argv.lock ? await readLock(argv) : {};
+        const hashes = lock[lockTarget(argv)] || {};
+        for (const obj of remote) {
+          const copy = template(withoutVersion(obj));
+          const hash = objectHash(obj);
+          if (local[key(obj)] && sameObject(local[key(obj)], copy, true)) {
+            if (hashes[key(obj)] !== hash) synced.push(obj);
+            continue;
+          }
+          // unchanged in Kibana since the lockfile, so the file was edited
+          if (local[key(obj)] && hashes[key(obj)] === hash) continue;
+          const file = names[key(obj)];
+          if (file && (ignored(file) || pending.has(file))) continue;
+          if (local[key(obj)]) {
+            await state.backupFiles([`${argv.dir}/${local[key(obj)].file}`]);
+          }
+          const name = await writeObjectFile(argv, argv.dir, copy, names);
+          pulled.set(name, Date.now());
+          written.push(obj);
+          logger.info(`Pulled ${key(obj)} into ${argv.dir}/${name}`);
+        }
+        await saveFileNames(argv.dir, names);
+        // what was pulled is what Kibana has, so pushing it back is no drift
+        await updateLock(argv, [...written, ...synced]);
+      });
+    } catch (err) {
+      logger.error(err.message);
+    }
+  };
+
+  await fs.mkdir(argv.dir, { recursive: true });
+  const names = await readFileNames(argv.dir);
+  const transform = await pushTransform(argv);
+  const template = await pullTransform(argv);
+  let ignored = await ignore.readIgnore(argv.dir);
+  const watcher = require('fs').watch(argv.dir, async (event, name) => {
+    if (name === ignore.ignoreFile) ignored = await ignore.readIgnore(argv.dir);
+    if (!name || path.extname(name) !== '.json' || ignored(name)) return;
+    if (Date.now() - (pulled.get(name) || 0) < quietPeriod) return;
+    pending.add(name);
+    clearTimeout(timer);
+    timer = setTimeout(push, argv.debounce);
+  });
+  logger.info(`Watching ${argv.dir} for changes`);
+
+  const poller = argv.poll && setInterval(pull, argv.poll * 1000);
+  return {
+    close() {
+      watcher.close();
+      clearInterval(poller);
+      clearTimeout(timer);
+    },
+  };
+}
+
+// List the type, id, title and file of each object in a directory
+async function listObjects(argv) {
+  try {
+    return (await readObjects(argv.dir))
+      .filter((obj) => !argv.types || argv.types.includes(obj.type))
+      .map((obj) => ({
+        type: obj.type,
+        id: obj.id,
+        title: objectTitle(obj),
+        file: obj.file,
+      }));
+  } catch (err) {
+    commandError(err);
+  }
+}
+
+// Build the reference graph of the objects in a directory, or part of it
+async function referenceGraph(argv) {
+  try {
+    const objects = (await readObjects(argv.dir)).map((obj) => ({
+      ...obj,
+      title: objectTitle(obj),
+    }));
+    const all = graph.buildGraph(objects);
+    if (!argv.root) return all;
+    if (!all.nodes[argv.root]) {
+      throw new KibobError('NOT_FOUND', `${argv.root} is not in ${argv.dir}`);
+    }
+    return graph.subgraph(all, argv.root);
+  } catch (err) {
+    commandError(err);
+  }
+}
+
+// Print objects as a table
+function printList(objects) {
+  printTable(['type', 'id', 'title', 'file'], objects);
+}
+
+// Print the given fields of each object as a table with aligned columns
+function printTable(columns, objects) {
+  const rows = [
+    columns.map((column) => column.toUpperCase()),
+    ...objects.map((obj) => columns.map((column) => String(obj[column]))),
+  ];
+  const widths = columns.map((column, i) =>
+    Math.max(...rows.map((row) => row[i].length))
+  );
+  for (const row of rows) {
+    console.log(row.map((cell, i) => cell.padEnd(widths[i])).join('  '));
+  }
+}
+
+// Fetch a Kibana API path, with any query, and return its status and JSON
+async function getJson(argv, pathname) {
+  const url = new URL(pathname, argv.url);
+  const options = { method: 'GET', headers: kibanaHeaders(argv) };
+  const res = await withRetry(argv, () => kibanaFetch(argv, url, options));
+  const body = await res.json().catch(() => ({}));
+  return { status: res.status, statusText: res.statusText, body };
+}
+
+/* Run a series of checks on the Kibana connection and local setup, each
+ * with a suggested fix when it fails. Exits non-zero if any check fails.
+ */
+async function doctor(argv) {
+  const checks = [];
+  const check = async (name, run) => {
+    try {
+      const fix = await run();
+      checks.push({ check: name, ok: !fix, fix });
+    } catch (err) {
+      checks.push({ check: name, ok: false, fix: err.message });
+    }
+  };
+
+  await check('remotes.json', async () => {
+    try {
+      JSON.parse(await fs.readFile('remotes.json', 'utf8'));
+    } catch (err) {
+      if (err.code === 'ENOENT') return;
+      return `Fix the JSON syntax in remotes.json: ${err.message}`;
+    }
+  });
+
+  await check(`Kibana at ${new URL(argv.url).host}`, async () => {
+    const { status } = await getJson(argv, '/api/status');
+    if (status === 401) return 'Check the credentials in the URL or remote';
+    if (status !== 200) return `Kibana returned ${status} for /api/status`;
+    const { number, major } = await kibanaVersion(argv);
+    if (number && !testedMajors.includes(major)) {
+      return `kibob is tested against Kibana 7.x and 8.x, this is ${number}`;
+    }
+  });
+
+  if (argv.space) {
+    await check(`space ${argv.space}`, async () => {
+      const { status } = await getJson(argv, `/api/spaces/space/${argv.space}`);
+      if (status === 404) return `Create the '${argv.space}' space in Kibana`;
+      if (status !== 200) return `Kibana returned ${status} for the space`;
+    });
+  }
+
+  await check(`objects in ${argv.dir}`, async () => {
+    const files = [];
+    for (const file of await jsonFiles(argv.dir)) {
+      const text = await fs.readFile(`${argv.dir}/${file}`, 'utf8');
+      files.push({ file, text });
+    }
+    const problems = validateFiles(files);
+    if (problems.length > 0) {
+      return `${problems.length} problems, run 'kibob validate -d ${argv.dir}'`;
+    }
+  });
+
+  await check(`git status of ${argv.dir}`, async () => {
+    let dirty;
+    try {
+      dirty = await git(argv.dir, 'status', '--porcelain', '.');
+    } catch (err) {
+      return `Track ${argv.dir} in a git repository to version its objects`;
+    }
+    if (dirty.trim()) return 'Commit or stash local changes before exporting';
+  });
+
+  await check(`${state.stateDir} lock`, async () => {
+    const holder = await state.lockHolder();
+    if (!holder) return;
+    return (
+      `Held by \`${holder.command}\` (pid ${holder.pid} on ${holder.host}) ` +
+      `since ${holder.started}, remove ${state.stateDir}/lock if it is stuck`
+    );
+  });
+
+  if (checks.some((result) => !result.ok)) fail();
+  return checks;
 }
 
-/* Loads saved objects from a saved_objects.ndjson file and calls the Kibana
- * create saved objects API to import them.
- * https://www.elastic.co/guide/en/kibana/master/saved-objects-api-import.html
+// Print each check with a tick or a cross and its suggested fix
+function printChecks(checks) {
+  for (const { check, ok, fix } of checks) {
+    console.log(ok ? `\u2714 ${check}` : `\u2718 ${check}: ${fix}`);
+  }
+}
+
+// Every owner's case settings in the space, by owner
+async function caseConfigs(argv) {
+  const pathname = spacePath(argv, '/api/cases/configure');
+  const { status, statusText, body } = await getJson(argv, pathname);
+  if (status !== 200) {
+    throw apiError(status, statusText, body);
+  }
+  return R.indexBy(R.prop('owner'), body);
+}
+
+// Save each owner's case settings, templates and custom fields to a file
+// https://www.elastic.co/guide/en/kibana/current/get-case-configuration-api.html
+async function exportCases(argv) {
+  try {
+    const settings = R.values(await caseConfigs(argv)).map(
+      R.pick(['owner', ...caseSettings])
+    );
+    await state.writeAtomic(
+      argv.file,
+      JSON.stringify(settings, null, 2) + '\n'
+    );
+    logger.info(`Saved settings of ${settings.length} owners to ${argv.file}`);
+    return { file: argv.file, count: settings.length };
+  } catch (err) {
+    commandError(err);
+  }
+}
+
+/* Create the case settings of owners that have none in the space, and
+ * update the rest with the version Kibana currently has.
  */
+async function importCases(argv) {
+  const results = [];
+  try {
+    const settings = JSON.parse(await fs.readFile(argv.file, 'utf8'));
+    const existing = await caseConfigs(argv);
+    for (const config of settings) {
+      const current = existing[config.owner];
+      const url = new URL(argv.url);
+      url.pathname = spacePath(
+        argv,
+        current ? `/api/cases/configure/${current.id}` : '/api/cases/configure'
+      );
+      const body = current
+        ? { ...R.pick(caseSettings, config), version: current.version }
+        : R.pick(['owner', ...caseSettings], config);
+      const options = {
+        method: current ? 'PATCH' : 'POST',
+        headers: { ...kibanaHeaders(argv), 'Content-Type': 'application/json' },
+        body: JSON.stringify(body),
+      };
+      const res = await withRetry(argv, () => kibanaFetch(argv, url, options));
+      const { owner } = config;
+      if (res.status === 200) {
+        results.push({ owner, action: current ? 'updated' : 'created' });
+      } else {
+        const error = JSON.stringify(await res.json());
+        logger.error(`${owner}: ${res.status} ${res.statusText} ${error}`);
+        results.push({ owner, action: 'failed' });
+        fail();
+      }
+    }
+  } catch (err) {
+    commandError(err);
+  }
+  return results;
+}
 
-async function importObjects(argv) {
-  const url = new URL(argv.url);
-  url.search = argv.overwrite ? '?overwrite=true' : '';
-  url.pathname = `/api/saved_objects/_import`;
-
-  const buffer = await fs.readFile(argv.file, 'binary');
-  const form = new FormData();
-  form.append('file', buffer, {
-    contentType: 'text/plain',
-    name: 'file',
-    filename: argv.file,
-  });
+// Role fields that can be written back through the role API
+const roleFields = ['elasticsearch', 'kibana', 'metadata'];
 
-  logger.info('loading saved objects from ' + argv.file);
-  const options = {
-    method: 'POST',
-    headers: {
-      'kbn-xsrf': true,
-      form: form.getHeaders(),
-    },
-  };
+const reservedRole = (role) => R.pathOr(false, ['metadata', '_reserved'], role);
+
+// Whether a role grants Kibana privileges in a space, or in every space
+const roleInSpace = (space) => (role) =>
+  (role.kibana || []).some(
+    (entry) => entry.spaces.includes(space) || entry.spaces.includes('*')
+  );
 
+// Save the roles defined by users, optionally only those used by --space
+// https://www.elastic.co/guide/en/kibana/current/role-management-api-get.html
+async function exportRoles(argv) {
   try {
-    const res = await fetch(url, { ...options, body: form });
-    const body = JSON.stringify(await res.json(), null, 2);
-    if (res.status === 200) {
-      logger.info(`${res.status} ${res.statusText} Response:\n${body}`);
-    } else {
-      logger.error(`${res.status} ${res.statusText} Error: ${body}`);
+    const pathname = '/api/security/role';
+    const { status, statusText, body } = await getJson(argv, pathname);
+    if (status !== 200) {
+      throw apiError(status, statusText, body);
     }
-  } catch (FetchError) {
-    logger.error(`${FetchError.message}`);
+    const roles = body
+      .filter((role) => !reservedRole(role))
+      .filter(argv.space ? roleInSpace(argv.space) : R.T)
+      .map(R.pick(['name', ...roleFields]));
+    await state.writeAtomic(argv.file, JSON.stringify(roles, null, 2) + '\n');
+    logger.info(`Saved ${roles.length} roles to ${argv.file}`);
+    return { file: argv.file, count: roles.length };
+  } catch (err) {
+    commandError(err);
   }
 }
 
-// Export Kibana saved objects with the find API
-// https://www.elastic.co/guide/en/kibana/current/saved-objects-api-find.html
-async function findObjects(argv) {
-  let body = {};
-  const url = new URL(argv.url);
-  url.pathname = '/api/saved_objects/_find';
-  url.search = '?per_page=1000';
-  for (const type of argv.types) url.search += `&type=${type}`;
-  url.search;
-  url.search += argv.search ? `&search=${argv.search}` : '';
+// Create or replace each role in a file, refusing to touch reserved roles
+// https://www.elastic.co/guide/en/kibana/current/role-management-api-put.html
+async function importRoles(argv) {
+  const results = [];
+  try {
+    const roles = JSON.parse(await fs.readFile(argv.file, 'utf8'));
+    const save = async (role) => {
+      const { name } = role;
+      if (reservedRole(role)) {
+        logger.warn(`${name}: skipping reserved role`);
+        return { name, action: 'skipped' };
+      }
+      const url = new URL(argv.url);
+      url.pathname = `/api/security/role/${encodeURIComponent(name)}`;
+      const options = {
+        method: 'PUT',
+        headers: { ...kibanaHeaders(argv), 'Content-Type': 'application/json' },
+        body: JSON.stringify(R.pick(roleFields, role)),
+      };
+      const res = await withRetry(argv, () => kibanaFetch(argv, url, options));
+      if (res.ok) {
+        return { name, action: 'saved' };
+      }
+      const error = JSON.stringify(await res.json());
+      logger.error(`${name}: ${res.status} ${res.statusText} ${error}`);
+      fail();
+      return { name, action: 'failed' };
+    };
+    results.push(...(await mapLimit(argv.concurrency, roles, save)));
+  } catch (err) {
+    commandError(err);
+  }
+  return results;
+}
 
-  logger.verbose('url.search: ' + url.search);
+/* SLO fields that define it. The rest, like revision, summary and the
+ * timestamps, are managed by Kibana along with the transforms it generates.
+ */
+const sloFields = [
+  'name',
+  'description',
+  'indicator',
+  'timeWindow',
+  'budgetingMethod',
+  'objective',
+  'settings',
+  'groupBy',
+  'tags',
+];
 
-  const options = {
-    method: 'GET',
-    headers: {
-      'kbn-xsrf': true,
-    },
+// Save the SLO definitions of a space, without their generated state
+// https://www.elastic.co/guide/en/kibana/current/slo-api-find.html
+async function exportSlos(argv) {
+  try {
+    const pathname = spacePath(argv, '/api/observability/slos');
+    const slos = {};
+    for (let page = 1; ; page++) {
+      const query = `${pathname}?page=${page}&perPage=100`;
+      const { status, statusText, body } = await getJson(argv, query);
+      if (status !== 200) {
+        throw apiError(status, statusText, body);
+      }
+      // grouped SLOs are listed once per instance
+      for (const slo of body.results) {
+        slos[slo.id] = R.pick(['id', ...sloFields], slo);
+      }
+      if (page * body.perPage >= body.total) break;
+    }
+    const definitions = R.sortBy(R.prop('id'), R.values(slos));
+    await state.writeAtomic(
+      argv.file,
+      JSON.stringify(definitions, null, 2) + '\n'
+    );
+    logger.info(`Saved ${definitions.length} SLOs to ${argv.file}`);
+    return { file: argv.file, count: definitions.length };
+  } catch (err) {
+    commandError(err);
+  }
+}
+
+// Create the SLOs missing from the space and update the rest, by id
+// https://www.elastic.co/guide/en/kibana/current/slo-api-create.html
+async function importSlos(argv) {
+  const results = [];
+  try {
+    const slos = JSON.parse(await fs.readFile(argv.file, 'utf8'));
+    const save = async (slo) => {
+      const { id, name } = slo;
+      const pathname = spacePath(argv, `/api/observability/slos/${id}`);
+      const exists = (await getJson(argv, pathname)).status === 200;
+      const url = new URL(argv.url);
+      url.pathname = exists
+        ? pathname
+        : spacePath(argv, '/api/observability/slos');
+      const options = {
+        method: exists ? 'PUT' : 'POST',
+        headers: { ...kibanaHeaders(argv), 'Content-Type': 'application/json' },
+        body: JSON.stringify(
+          R.pick(exists ? sloFields : ['id', ...sloFields], slo)
+        ),
+      };
+      const res = await withRetry(argv, () => kibanaFetch(argv, url, options));
+      if (res.ok) {
+        return { id, name, action: exists ? 'updated' : 'created' };
+      }
+      const error = JSON.stringify(await res.json());
+      logger.error(`${id}: ${res.status} ${res.statusText} ${error}`);
+      fail();
+      return { id, name, action: 'failed' };
+    };
+    results.push(...(await mapLimit(argv.concurrency, slos, save)));
+  } catch (err) {
+    commandError(err);
+  }
+  return results;
+}
+
+const connectorFields = ['id', 'name', 'connector_type_id', 'config'];
+
+// Secret fields of each connector type, which Kibana never returns
+const connectorSecrets = {
+  '.bedrock': ['accessKey', 'secret'],
+  '.cases-webhook': ['user', 'password'],
+  '.crowdstrike': ['clientId', 'clientSecret'],
+  '.d3security': ['token'],
+  '.email': ['user', 'password', 'clientSecret'],
+  '.gemini': ['credentialsJson'],
+  '.gen-ai': ['apiKey'],
+  '.index': [],
+  '.jira': ['email', 'apiToken'],
+  '.opsgenie': ['apiKey'],
+  '.pagerduty': ['routingKey'],
+  '.resilient': ['apiKeyId', 'apiKeySecret'],
+  '.sentinelone': ['token'],
+  '.server-log': [],
+  '.servicenow': ['username', 'password'],
+  '.servicenow-itom': ['username', 'password'],
+  '.servicenow-sir': ['username', 'password'],
+  '.slack': ['webhookUrl'],
+  '.slack_api': ['token'],
+  '.swimlane': ['apiToken'],
+  '.teams': ['webhookUrl'],
+  '.thehive': ['apiKey'],
+  '.tines': ['email', 'token'],
+  '.torq': ['token'],
+  '.webhook': ['user', 'password'],
+  '.xmatters': ['user', 'password', 'secretsUrl'],
+};
+
+// The environment variable for a connector's secret, e.g. OPS_SLACK_WEBHOOK_URL
+function secretName(connector, field) {
+  const words = (text) =>
+    text
+      .replace(/([a-z0-9])([A-Z])/g, '$1_$2')
+      .replace(/[^A-Za-z0-9]+/g, '_')
+      .replace(/^_|_$/g, '');
+  return `${words(connector.name)}_${words(field)}`.toUpperCase();
+}
+
+/* Export the connectors of the space, with a {{ NAME }} placeholder for each
+ * secret. Connectors already in the file keep their secrets, so placeholders
+ * renamed by hand survive the next export. Preconfigured and system
+ * connectors belong to kibana.yml and are left out.
+ * https://www.elastic.co/guide/en/kibana/current/get-all-connectors-api.html
+ */
+async function exportConnectors(argv) {
+  try {
+    const pathname = spacePath(argv, '/api/actions/connectors');
+    const { status, statusText, body } = await getJson(argv, pathname);
+    if (status !== 200) {
+      throw apiError(status, statusText, body);
+    }
+    let saved = [];
+    try {
+      saved = JSON.parse(await fs.readFile(argv.file, 'utf8'));
+    } catch (err) {
+      if (err.code !== 'ENOENT') throw err;
+    }
+    const savedSecrets = R.fromPairs(
+      saved.map((connector) => [connector.id, connector.secrets])
+    );
+    const connectors = body
+      .filter((connector) => !connector.is_preconfigured)
+      .filter((connector) => !connector.is_system_action)
+      .map((connector) => {
+        const type = connector.connector_type_id;
+        if (!connectorSecrets[type] && !savedSecrets[connector.id]) {
+          logger.warn(
+            `${connector.id}: the secrets of ${type} connectors are not ` +
+              `known, add them to ${argv.file} as {{ NAME }} placeholders`
+          );
+        }
+        const fields = connectorSecrets[type] || [];
+        const placeholders = R.fromPairs(
+          fields.map((field) => [
+            field,
+            `{{ ${secretName(connector, field)} }}`,
+          ])
+        );
+        return {
+          ...R.pick(connectorFields, connector),
+          secrets: savedSecrets[connector.id] || placeholders,
+        };
+      });
+    const sorted = R.sortBy(R.prop('id'), connectors);
+    await state.writeAtomic(argv.file, JSON.stringify(sorted, null, 2) + '\n');
+    logger.info(`Saved ${sorted.length} connectors to ${argv.file}`);
+    return { file: argv.file, count: sorted.length };
+  } catch (err) {
+    commandError(err);
+  }
+}
+
+/* Create the connectors missing from the space and update the rest, by id,
+ * filling the placeholders in their secrets from environment variables. A
+ * connector with a placeholder that has no variable set is not sent, and
+ * neither is an update without secrets of a type whose secrets are unknown,
+ * which would clear those Kibana has.
+ * https://www.elastic.co/guide/en/kibana/current/create-connector-api.html
+ */
+async function importConnectors(argv) {
+  const results = [];
+  try {
+    const connectors = JSON.parse(await fs.readFile(argv.file, 'utf8'));
+    const save = async (connector) => {
+      const { id, name } = connector;
+      const secrets = connector.secrets || {};
+      const missing = transforms
+        .placeholderNames(secrets)
+        .filter((variable) => !R.has(variable, process.env));
+      if (missing.length > 0) {
+        logger.error(`${id}: set ${missing.join(', ')} for its secrets`);
+        fail();
+        return { id, name, action: 'failed' };
+      }
+      const pathname = spacePath(argv, `/api/actions/connector/${id}`);
+      const exists = (await getJson(argv, pathname)).status === 200;
+      const type = connector.connector_type_id;
+      if (exists && !connectorSecrets[type] && R.isEmpty(secrets)) {
+        logger.error(
+          `${id}: the secrets of ${type} connectors are not known, set them ` +
+            `in ${argv.file} as {{ NAME }} placeholders to update it`
+        );
+        fail();
+        return { id, name, action: 'failed' };
+      }
+      const url = new URL(argv.url);
+      url.pathname = pathname;
+      const fields = exists ? ['name', 'config'] : connectorFields.slice(1);
+      const options = {
+        method: exists ? 'PUT' : 'POST',
+        headers: { ...kibanaHeaders(argv), 'Content-Type': 'application/json' },
+        body: JSON.stringify({
+          ...R.pick(fields, connector),
+          secrets: transforms.fillTemplate(secrets, process.env),
+        }),
+      };
+      const res = await withRetry(argv, () => kibanaFetch(argv, url, options));
+      if (res.ok) {
+        return { id, name, action: exists ? 'updated' : 'created' };
+      }
+      const error = JSON.stringify(await res.json());
+      logger.error(`${id}: ${res.status} ${res.statusText} ${error}`);
+      fail();
+      return { id, name, action: 'failed' };
+    };
+    results.push(...(await mapLimit(argv.concurrency, connectors, save)));
+  } catch (err) {
+    commandError(err);
+  }
+  return results;
+}
+
+/* Alerting rule fields that define it. The rest, like the API key it runs
+ * with, its execution status and who changed it last, belong to Kibana.
+ */
+const ruleFields = [
+  'name',
+  'tags',
+  'rule_type_id',
+  'consumer',
+  'schedule',
+  'params',
+  'actions',
+  'notify_when',
+  'throttle',
+  'enabled',
+];
+
+// The fields an update takes, a rule's type and consumer never change
+const ruleUpdateFields = [
+  'name',
+  'tags',
+  'schedule',
+  'params',
+  'actions',
+  'notify_when',
+  'throttle',
+];
+
+// Security detection rules, which the detection engine API manages
+const detectionRule = (rule) => rule.consumer === 'siem';
+
+// Save the alerting rules of a space, except security detection rules
+// https://www.elastic.co/guide/en/kibana/current/find-rules-api.html
+async function exportRules(argv) {
+  try {
+    const pathname = spacePath(argv, '/api/alerting/rules/_find');
+    const rules = [];
+    for (let page = 1; ; page++) {
+      const query = `${pathname}?page=${page}&per_page=100`;
+      const { status, statusText, body } = await getJson(argv, query);
+      if (status !== 200) {
+        throw apiError(status, statusText, body);
+      }
+      rules.push(...body.data);
+      if (page * body.per_page >= body.total) break;
+    }
+    const definitions = rules
+      .filter((rule) => !detectionRule(rule))
+      .map((rule) => ({
+        id: rule.id,
+        ...R.pick(ruleFields, rule),
+        // Kibana gives every action a new uuid when the rule is saved
+        actions: (rule.actions || []).map(R.omit(['uuid'])),
+      }));
+    const sorted = R.sortBy(R.prop('id'), definitions);
+    await state.writeAtomic(argv.file, JSON.stringify(sorted, null, 2) + '\n');
+    logger.info(`Saved ${sorted.length} rules to ${argv.file}`);
+    return { file: argv.file, count: sorted.length };
+  } catch (err) {
+    commandError(err);
+  }
+}
+
+/* Create the rules missing from the space and update the rest, by id, then
+ * enable or disable them as the file says. Kibana runs a rule with an API
+ * key of whoever saved it last, here the user importing, and the connectors
+ * its actions use have to be imported first.
+ * https://www.elastic.co/guide/en/kibana/current/create-rule-api.html
+ */
+async function importRules(argv) {
+  const results = [];
+  const send = (pathname, method, body) => {
+    const url = new URL(argv.url);
+    url.pathname = pathname;
+    const options = {
+      method,
+      headers: { ...kibanaHeaders(argv), 'Content-Type': 'application/json' },
+      body: body && JSON.stringify(body),
+    };
+    return withRetry(argv, () => kibanaFetch(argv, url, options));
   };
+  try {
+    const rules = JSON.parse(await fs.readFile(argv.file, 'utf8'));
+    const save = async (rule) => {
+      const { id, name } = rule;
+      const pathname = spacePath(argv, `/api/alerting/rule/${id}`);
+      const current = await getJson(argv, pathname);
+      const exists = current.status === 200;
+      let res = exists
+        ? await send(pathname, 'PUT', R.pick(ruleUpdateFields, rule))
+        : await send(pathname, 'POST', R.pick(ruleFields, rule));
+      // an update leaves the rule enabled or disabled as it was
+      const toggle = rule.enabled ? '_enable' : '_disable';
+      if (res.ok && exists && R.has('enabled', rule)) {
+        if (current.body.enabled !== rule.enabled) {
+          res = await send(`${pathname}/${toggle}`, 'POST');
+        }
+      }
+      if (res.ok) {
+        return { id, name, action: exists ? 'updated' : 'created' };
+      }
+      const error = JSON.stringify(await res.json());
+      logger.error(`${id}: ${res.status} ${res.statusText} ${error}`);
+      fail();
+      return { id, name, action: 'failed' };
+    };
+    results.push(...(await mapLimit(argv.concurrency, rules, save)));
+  } catch (err) {
+    commandError(err);
+  }
+  return results;
+}
+
+/* Data view fields that define it. Its field list is read from the indices
+ * it matches, so only the formats, attributes and runtime fields added in
+ * Kibana are kept.
+ */
+const dataViewFields = [
+  'title',
+  'name',
+  'timeFieldName',
+  'sourceFilters',
+  'fieldFormats',
+  'fieldAttrs',
+  'runtimeFieldMap',
+  'allowNoIndex',
+  'type',
+  'typeMeta',
+];
 
+/* Save the data views of a space, with the scripts of runtime fields split
+ * into lines for readable diffs.
+ * https://www.elastic.co/guide/en/kibana/current/data-views-api-get-all.html
+ */
+async function exportDataViews(argv) {
   try {
-    const res = await fetch(url, { ...options });
-    body = await res.json();
-    if (res.status === 200) {
-      logger.info(
-        `${res.status} ${res.statusText} Found: ${body.saved_objects.length} objects`
-      );
-    } else {
-      logger.error(`${res.status} ${res.statusText} Error: ${body}`);
+    const list = await getJson(argv, spacePath(argv, '/api/data_views'));
+    if (list.status !== 200) {
+      throw apiError(list.status, list.statusText, list.body);
     }
-  } catch (FetchError) {
-    logger.error(`${FetchError.message}`);
+    const read = async ({ id }) => {
+      const pathname = spacePath(argv, `/api/data_views/data_view/${id}`);
+      const { status, statusText, body } = await getJson(argv, pathname);
+      if (status !== 200) {
+        throw apiError(status, statusText, body);
+      }
+      const dataView = { id, ...R.pick(dataViewFields, body.data_view) };
+      return transforms.splitRuntimeScripts(dataView);
+    };
+    const views = list.body.data_view;
+    const dataViews = await mapLimit(argv.concurrency, views, read);
+    const sorted = R.sortBy(R.prop('id'), dataViews);
+    await state.writeAtomic(argv.file, JSON.stringify(sorted, null, 2) + '\n');
+    logger.info(`Saved ${sorted.length} data views to ${argv.file}`);
+    return { file: argv.file, count: sorted.length };
+  } catch (err) {
+    commandError(err);
   }
-  return body.saved_objects;
 }
 
-// Write an array of JSON objects into an .ndjson file
-async function saveObjects(filename, saved_objects) {
-  const withoutVersion = R.omit(['version', 'updated_at']);
-  let text = '';
+// Create the data views missing from the space and update the rest, by id
+// https://www.elastic.co/guide/en/kibana/current/data-views-api-create.html
+async function importDataViews(argv) {
+  const results = [];
+  try {
+    const dataViews = JSON.parse(await fs.readFile(argv.file, 'utf8'));
+    const save = async (dataView) => {
+      const { id, name = dataView.title } = dataView;
+      const pathname = spacePath(argv, `/api/data_views/data_view/${id}`);
+      const exists = (await getJson(argv, pathname)).status === 200;
+      const url = new URL(argv.url);
+      url.pathname = exists
+        ? pathname
+        : spacePath(argv, '/api/data_views/data_view');
+      const fields = exists ? dataViewFields : ['id', ...dataViewFields];
+      const joined = transforms.joinRuntimeScripts(dataView);
+      const options = {
+        method: 'POST',
+        headers: { ...kibanaHeaders(argv), 'Content-Type': 'application/json' },
+        body: JSON.stringify({ data_view: R.pick(fields, joined) }),
+      };
+      const res = await withRetry(argv, () => kibanaFetch(argv, url, options));
+      if (res.ok) {
+        return { id, name, action: exists ? 'updated' : 'created' };
+      }
+      const error = JSON.stringify(await res.json());
+      logger.error(`${id}: ${res.status} ${res.statusText} ${error}`);
+      fail();
+      return { id, name, action: 'failed' };
+    };
+    results.push(...(await mapLimit(argv.concurrency, dataViews, save)));
+  } catch (err) {
+    commandError(err);
+  }
+  return results;
+}
 
-  saved_objects.forEach((obj) => {
-    text += JSON.stringify(withoutVersion(obj)) + '\n';
-  });
+// Fields of detection rules and exception lists that Kibana keeps up to date
+const detectionVolatile = [
+  'created_at',
+  'created_by',
+  'updated_at',
+  'updated_by',
+  'execution_summary',
+  'revision',
+  'tie_breaker_id',
+  '_version',
+];
+
+// Sort key of a line of a detection rules export: a rule, an exception list
+// or list item, or a connector used by a rule's actions
+function detectionKey(item) {
+  if (item.rule_id) return `rule/${item.rule_id}`;
+  if (item.item_id) return `item/${item.list_id}/${item.item_id}`;
+  if (item.list_id) return `list/${item.list_id}`;
+  return `${item.type}/${item.id}`;
+}
+
+/* Save the custom detection rules of a space, along with the exception lists
+ * and connectors they use, from the detection engine's own export. Prebuilt
+ * rules come with Kibana and are left out.
+ * https://www.elastic.co/guide/en/security/current/rules-api-export.html
+ */
+async function exportDetectionRules(argv) {
   try {
-    const data = new Uint8Array(Buffer.from(text));
-    await fs.writeFile(filename, data);
+    const findPath = spacePath(argv, '/api/detection_engine/rules/_find');
+    const custom = 'alert.attributes.params.immutable: false';
+    const filter = encodeURIComponent(custom);
+    const ruleIds = [];
+    for (let page = 1; ; page++) {
+      const query = `${findPath}?page=${page}&per_page=100&filter=${filter}`;
+      const { status, statusText, body } = await getJson(argv, query);
+      if (status !== 200) {
+        throw apiError(status, statusText, body);
+      }
+      ruleIds.push(...body.data.map((rule) => ({ rule_id: rule.rule_id })));
+      if (page * body.perPage >= body.total) break;
+    }
 
-    logger.info(`Saved ${saved_objects.length} objects to ${filename}`);
+    let items = [];
+    if (ruleIds.length > 0) {
+      const url = new URL(argv.url);
+      url.pathname = spacePath(argv, '/api/detection_engine/rules/_export');
+      url.search = '?exclude_export_details=true';
+      const options = {
+        method: 'POST',
+        headers: { ...kibanaHeaders(argv), 'Content-Type': 'application/json' },
+        body: JSON.stringify({ objects: ruleIds }),
+      };
+      const res = await withRetry(argv, () => kibanaFetch(argv, url, options));
+      const body = await res.text();
+      if (!res.ok) {
+        const error = body.startsWith('{') ? JSON.parse(body) : body;
+        throw apiError(res.status, res.statusText, error);
+      }
+      items = body
+        .split('\n')
+        .filter((line) => line.trim())
+        .map((line) => R.omit(detectionVolatile, JSON.parse(line)));
+    }
+    const sorted = R.sortBy(detectionKey, items);
+    await state.writeAtomic(argv.file, JSON.stringify(sorted, null, 2) + '\n');
+    logger.info(`Saved ${ruleIds.length} detection rules to ${argv.file}`);
+    return { file: argv.file, count: ruleIds.length };
   } catch (err) {
-    logger.error(`${filename}: [${err.name}] ${err.message}`);
-    throw err;
+    commandError(err);
   }
 }
 
-// Export Kibana saved objects from the _export API
-// https://www.elastic.co/guide/en/kibana/current/saved-objects-api-export.html
-async function exportObjects(argv) {
-  const url = new URL(argv.url);
-  url.pathname = '/api/saved_objects/_export';
+/* Import a file of detection rules with the detection engine's import,
+ * overwriting rules, exception lists and connectors with the same ids. Value
+ * lists used by exceptions are not part of it and have to exist already.
+ * https://www.elastic.co/guide/en/security/current/rules-api-import.html
+ */
+async function importDetectionRules(argv) {
+  const results = [];
+  try {
+    const items = JSON.parse(await fs.readFile(argv.file, 'utf8'));
+    const ndjson = items.map((item) => JSON.stringify(item) + '\n').join('');
+    const url = new URL(argv.url);
+    url.pathname = spacePath(argv, '/api/detection_engine/rules/_import');
+    url.search =
+      '?overwrite=true&overwrite_exceptions=true' +
+      '&overwrite_action_connectors=true';
+    // the form is read as it is sent, so a retry needs a new one
+    const send = () => {
+      const form = new FormData();
+      form.append('file', Buffer.from(ndjson), {
+        contentType: 'application/ndjson',
+        name: 'file',
+        filename: 'rules.ndjson',
+      });
+      return kibanaFetch(argv, url, {
+        method: 'POST',
+        headers: { ...kibanaHeaders(argv), ...form.getHeaders() },
+        body: form,
+      });
+    };
+    const res = await withRetry(argv, send);
+    const body = await res.json();
+    if (!res.ok) {
+      throw apiError(res.status, res.statusText, body);
+    }
 
-  const options = {
-    method: 'GET',
-    headers: {
-      'kbn-xsrf': true,
-    },
+    const errors = R.indexBy(R.prop('rule_id'), body.errors || []);
+    for (const { rule_id, name } of items.filter((item) => item.rule_id)) {
+      const error = errors[rule_id];
+      if (error) {
+        logger.error(`${rule_id}: ${error.error.message}`);
+        fail();
+      }
+      results.push({ rule_id, name, action: error ? 'failed' : 'imported' });
+    }
+    for (const error of body.exceptions_errors || []) {
+      logger.error(`Exception list ${error.list_id}: ${error.error.message}`);
+    }
+    for (const error of body.action_connectors_errors || []) {
+      logger.error(`Connector ${error.id}: ${error.error.message}`);
+    }
+  } catch (err) {
+    commandError(err);
+  }
+  return results;
+}
+
+const agentPolicyFields = [
+  'id',
+  'name',
+  'namespace',
+  'description',
+  'monitoring_enabled',
+  'inactivity_timeout',
+  'unenroll_timeout',
+  'data_output_id',
+  'monitoring_output_id',
+  'fleet_server_host_id',
+];
+const packagePolicyFields = [
+  'id',
+  'name',
+  'namespace',
+  'description',
+  'policy_id',
+  'package',
+  'vars',
+  'inputs',
+];
+
+// Fields Fleet compiles from the rest of a package policy, at any depth
+const compiledFields = ['compiled_input', 'compiled_stream'];
+
+function omitCompiled(value) {
+  if (Array.isArray(value)) return value.map(omitCompiled);
+  if (value && typeof value === 'object') {
+    return R.map(omitCompiled, R.omit(compiledFields, value));
+  }
+  return value;
+}
+
+/* Replace the secrets in a package policy's vars, at any depth, with
+ * {{ <policy id>.<var> }} placeholders for fleet-import --values to fill in.
+ */
+function scrubSecrets(policy) {
+  const scrub = (value) => {
+    if (Array.isArray(value)) return value.map(scrub);
+    if (!value || typeof value !== 'object') return value;
+    return R.mapObjIndexed((child, key) => {
+      const secret =
+        child &&
+        typeof child === 'object' &&
+        'value' in child &&
+        (child.type === 'password' ||
+          R.pathOr(false, ['value', 'isSecretRef'], child));
+      if (!secret) return scrub(child);
+      return { ...child, value: `{{ ${policy.id}.${key} }}` };
+    }, value);
   };
+  return scrub(policy);
+}
+
+// Every page of items of a Fleet list API
+async function fleetItems(argv, pathname) {
+  const items = [];
+  for (let page = 1; ; page++) {
+    const query = `${spacePath(argv, pathname)}?page=${page}&perPage=100`;
+    const { status, statusText, body } = await getJson(argv, query);
+    if (status !== 200) {
+      throw apiError(status, statusText, body);
+    }
+    items.push(...body.items);
+    if (page * body.perPage >= body.total) break;
+  }
+  return items;
+}
 
+// Save the agent policies and integration (package) policies of a space,
+// leaving out policies managed by Elastic and scrubbing secrets
+// https://www.elastic.co/guide/en/fleet/current/fleet-api-docs.html
+async function exportFleet(argv) {
   try {
-    const res = await fetch(url, { ...options });
-    const body = JSON.stringify(await res.json(), null, 2);
-    if (res.status === 200) {
-      logger.info(`${res.status} ${res.statusText} Response:\n${body}`);
+    const agentPolicies = (await fleetItems(argv, '/api/fleet/agent_policies'))
+      .filter((policy) => !policy.is_managed)
+      .map(R.pick(agentPolicyFields));
+    const ids = agentPolicies.map(R.prop('id'));
+    const packagePolicies = (
+      await fleetItems(argv, '/api/fleet/package_policies')
+    )
+      .filter((policy) => ids.includes(policy.policy_id))
+      .map((policy) => {
+        const picked = R.pick(packagePolicyFields, policy);
+        const pkg = R.pick(['name', 'title', 'version'], policy.package);
+        return scrubSecrets(omitCompiled({ ...picked, package: pkg }));
+      });
+
+    const fleet = { agentPolicies, packagePolicies };
+    await state.writeAtomic(argv.file, JSON.stringify(fleet, null, 2) + '\n');
+    const count = agentPolicies.length + packagePolicies.length;
+    logger.info(`Saved ${count} Fleet policies to ${argv.file}`);
+    return { file: argv.file, count };
+  } catch (err) {
+    commandError(err);
+  }
+}
+
+/* Create the Fleet policies missing from the space and update the rest, by
+ * id. Agent policies go first, since integration policies belong to them.
+ */
+async function importFleet(argv) {
+  const results = [];
+  const save = async (kind, pathname, policy) => {
+    const { id, name } = policy;
+    const itemPath = spacePath(argv, `${pathname}/${id}`);
+    const exists = (await getJson(argv, itemPath)).status === 200;
+    const url = new URL(argv.url);
+    url.pathname = exists ? itemPath : spacePath(argv, pathname);
+    const options = {
+      method: exists ? 'PUT' : 'POST',
+      headers: { ...kibanaHeaders(argv), 'Content-Type': 'application/json' },
+      body: JSON.stringify(exists ? R.omit(['id'], policy) : policy),
+    };
+    const res = await withRetry(argv, () => kibanaFetch(argv, url, options));
+    if (res.ok) {
+      results.push({ kind, id, name, action: exists ? 'updated' : 'created' });
     } else {
-      logger.error(`${res.status} ${res.statusText} Error: ${body}`);
+      const error = JSON.stringify(await res.json());
+      logger.error(`${kind} ${id}: ${res.status} ${res.statusText} ${error}`);
+      results.push({ kind, id, name, action: 'failed' });
+      fail();
     }
-  } catch (FetchError) {
-    logger.error(`${FetchError.message}`);
+  };
+
+  try {
+    const fleet = JSON.parse(await fs.readFile(argv.file, 'utf8'));
+    const values = argv.values && (await readJsonFile(argv.values));
+    const fill = (policy) =>
+      values ? transforms.fillTemplate(policy, values) : policy;
+    for (const policy of fleet.agentPolicies || []) {
+      await save('agent', '/api/fleet/agent_policies', fill(policy));
+    }
+    for (const policy of fleet.packagePolicies || []) {
+      await save('integration', '/api/fleet/package_policies', fill(policy));
+    }
+  } catch (err) {
+    commandError(err);
   }
+  return results;
 }
 
-// Convert .ndjson file into separate .json files
-async function unbundleObjects(argv) {
-  const path = argv.dir;
+/* Save the short URLs that objects link to with /goto/, such as in URL
+ * drilldowns, as the locator each one opens, keyed by slug.
+ * https://www.elastic.co/guide/en/kibana/current/short-urls-api.html
+ */
+async function exportShortUrls(argv, objects) {
   try {
-    const buffer = await fs.readFile(argv.file, 'binary');
-    await fs.mkdir(path, { recursive: true });
-
-    buffer.split('\n').forEach(async (obj) => {
-      try {
-        const json = obj && JSON.parse(obj);
-        if (json.type) {
-          const filename = `${json.attributes.title}.${json.type}.json`;
-          logger.debug(filename);
-          const data = new Uint8Array(
-            Buffer.from(JSON.stringify(json, null, 2))
-          );
-          await fs.writeFile(`${path}/${filename}`, data);
-        }
-      } catch (SyntaxError) {
-        console.log(SyntaxError);
-        logger.debug(`Failed to parse: ${SyntaxError}`);
+    const slugs = R.uniq(R.chain(transforms.shortUrlSlugs, objects)).sort();
+    const urls = {};
+    await mapLimit(argv.concurrency, slugs, async (slug) => {
+      const api = spacePath(argv, `/api/short_url/_slug/${slug}`);
+      const { status, statusText, body } = await getJson(argv, api);
+      if (status === 404) {
+        logger.warn(`Short URL ${slug} is linked to but does not exist`);
+        return;
       }
+      if (status !== 200) throw apiError(status, statusText, body);
+      urls[slug] = R.pick(['id', 'version', 'state'], body.locator);
     });
+    await state.writeAtomic(
+      argv.shortUrls,
+      JSON.stringify(urls, null, 2) + '\n'
+    );
+    const count = Object.keys(urls).length;
+    logger.info(`Saved ${count} short URLs to ${argv.shortUrls}`);
   } catch (err) {
-    logger.error(err);
+    commandError(err);
   }
 }
 
-// Convert directory of .json files into single .ndjson
-async function bundleObjects(argv) {
-  let output = '';
-  let i = 0;
+/* Create the short URLs in a file that the space does not have yet, with
+ * their old slugs where those are free. Returns a map of old slugs to the
+ * new ones for rewriting links, or nothing on failure.
+ */
+async function importShortUrls(argv) {
   try {
-    const dir = await fs.opendir(argv.dir);
-    for await (const dirent of dir) {
-      const buffer = await fs.readFile(`${argv.dir}/${dirent.name}`, 'binary');
-      logger.debug(`Bundling '${argv.dir}/${dirent.name}'`);
-      output += JSON.stringify(JSON.parse(buffer)) + '\n';
-      i++;
+    const urls = JSON.parse(await fs.readFile(argv.shortUrls, 'utf8'));
+    const slugs = {};
+    const url = new URL(argv.url);
+    url.pathname = spacePath(argv, '/api/short_url');
+    for (const [slug, locator] of Object.entries(urls)) {
+      const api = spacePath(argv, `/api/short_url/_slug/${slug}`);
+      const existing = await getJson(argv, api);
+      const wanted = R.pick(['id', 'state'], locator);
+      if (
+        existing.status === 200 &&
+        R.equals(R.pick(['id', 'state'], existing.body.locator), wanted)
+      ) {
+        continue;
+      }
+
+      // another link may already hold the slug, then Kibana picks a new one
+      const create = async (params) => {
+        const options = {
+          method: 'POST',
+          headers: {
+            ...kibanaHeaders(argv),
+            'Content-Type': 'application/json',
+          },
+          body: JSON.stringify({
+            locatorId: locator.id,
+            params: locator.state,
+            ...params,
+          }),
+        };
+        return withRetry(argv, () => kibanaFetch(argv, url, options));
+      };
+      let res = existing.status === 404 ? await create({ slug }) : undefined;
+      if (!res || res.status === 409) res = await create({});
+      const body = await res.json();
+      if (!res.ok) throw apiError(res.status, res.statusText, body);
+      if (body.slug !== slug) slugs[slug] = body.slug;
+      logger.info(`Created short URL ${body.slug} for ${slug}`);
     }
-    const data = new Uint8Array(Buffer.from(output));
-    await fs.writeFile(argv.file, data);
-    logger.info(`Wrote ${i} objects to ${argv.file}`);
+    return slugs;
+  } catch (err) {
+    commandError(err);
+  }
+}
+
+// The advanced settings to manage: --keys, or the "settings" list of
+// kibob.json, or every setting changed from its default when neither is set
+function settingKeys(argv) {
+  return argv.keys || readProjectConfig().settings;
+}
+
+// Save the advanced settings of a space that differ from their defaults
+// https://www.elastic.co/guide/en/kibana/current/advanced-options.html
+async function exportSettings(argv) {
+  try {
+    const pathname = spacePath(argv, '/api/kibana/settings');
+    const { status, statusText, body } = await getJson(argv, pathname);
+    if (status !== 200) throw apiError(status, statusText, body);
+
+    const keys = settingKeys(argv);
+    const settings = {};
+    for (const [key, setting] of Object.entries(body.settings)) {
+      // buildNum is Kibana's own, overridden ones come from kibana.yml
+      if (key === 'buildNum' || setting.isOverridden) continue;
+      // null for a listed setting that is left at its default
+      const changed = 'userValue' in setting;
+      if (keys ? keys.includes(key) : changed) {
+        settings[key] = changed ? setting.userValue : null;
+      }
+    }
+    const sorted = transforms.normalizeObject(settings);
+    await state.writeAtomic(argv.file, JSON.stringify(sorted, null, 2) + '\n');
+    const count = Object.keys(sorted).length;
+    logger.info(`Saved ${count} settings to ${argv.file}`);
+    return { file: argv.file, count };
+  } catch (err) {
+    commandError(err);
+  }
+}
+
+// Apply the settings in a file to a space, a null value restores the default
+async function importSettings(argv) {
+  try {
+    const saved = JSON.parse(await fs.readFile(argv.file, 'utf8'));
+    const keys = settingKeys(argv);
+    const changes = keys ? R.pick(keys, saved) : saved;
+    const url = new URL(argv.url);
+    url.pathname = spacePath(argv, '/api/kibana/settings');
+    const options = {
+      method: 'POST',
+      headers: { ...kibanaHeaders(argv), 'Content-Type': 'application/json' },
+      body: JSON.stringify({ changes }),
+    };
+    const res = await withRetry(argv, () => kibanaFetch(argv, url, options));
+    if (!res.ok) {
+      throw apiError(res.status, res.statusText, await res.json());
+    }
+    const count = Object.keys(changes).length;
+    logger.info(`${res.status} ${res.statusText} Applied ${count} settings`);
+    return { file: argv.file, count };
+  } catch (err) {
+    commandError(err);
+  }
+}
+
+// Defaults for the options the command line would otherwise fill in
+const libraryDefaults = {
+  url: 'http://localhost:5601',
+  file: 'saved_objects.ndjson',
+  dir: 'saved_objects',
+  filename: '{title}.{type}.json',
+  types: defaultTypes,
+  drop: volatileFields,
+  references: true,
+  normalize: true,
+  expand: true,
+  overwrite: false,
+  force: false,
+  retries: 3,
+  retryDelay: 500,
+  batchSize: 1000,
+  concurrency: 4,
+  lock: 'kibob.lock',
+};
+
+/* Wrap a command so it takes the same options as the CLI, as camelCase keys,
+ * with defaults of its own for options like file that differ by command
+ */
+const withDefaults = (command, defaults = {}) => (options = {}, ...args) => {
+  libraryMode = true;
+  const argv = { ...libraryDefaults, ...defaults, ...options };
+  return command(resolveRemote(argv), ...args);
+};
+
+module.exports = {
+  bundle: withDefaults(bundleObjects),
+  unbundle: withDefaults(unbundleObjects),
+  split: withDefaults(splitObjects),
+  join: withDefaults(joinObjects),
+  exportObjects: withDefaults(async (argv) => {
+    const objects = argv.objects
+      ? await exportObjects(argv)
+      : await findObjects(argv);
+    if (objects && argv.shortUrls) await exportShortUrls(argv, objects);
+    return objects;
+  }),
+  saveObjects: withDefaults(saveObjects),
+  importObjects: withDefaults(importLocked),
+  planImport: withDefaults(planImport),
+  status: withDefaults(objectStatus),
+  diff: withDefaults(diffDirectory),
+  validate: withDefaults(validateDirectory),
+  list: withDefaults(listObjects),
+  clone: withDefaults(cloneObjects),
+  remove: withDefaults(removeObject),
+  rename: withDefaults(renameObject),
+  dedupe: withDefaults(dedupeObjects, { shared: '_shared' }),
+  graph: withDefaults(referenceGraph),
+  audit: withDefaults(auditObjects),
+  watch: withDefaults(watchDirectory, { debounce: 500, lockTimeout: 60 }),
+  snapshot: withDefaults(snapshotObjects, { dir: 'snapshots' }),
+  rollback: withDefaults(rollbackObjects, { dir: 'snapshots' }),
+  doctor: withDefaults(doctor),
+  devPush: withDefaults(devPush),
+  devClean: withDefaults(devClean),
+  exportCases: withDefaults(exportCases, { file: 'cases.json' }),
+  importCases: withDefaults(importCases, { file: 'cases.json' }),
+  exportRoles: withDefaults(exportRoles, { file: 'roles.json' }),
+  importRoles: withDefaults(importRoles, { file: 'roles.json' }),
+  exportSlos: withDefaults(exportSlos, { file: 'slos.json' }),
+  importSlos: withDefaults(importSlos, { file: 'slos.json' }),
+  exportConnectors: withDefaults(exportConnectors, { file: 'connectors.json' }),
+  importConnectors: withDefaults(importConnectors, { file: 'connectors.json' }),
+  exportRules: withDefaults(exportRules, { file: 'rules.json' }),
+  importRules: withDefaults(importRules, { file: 'rules.json' }),
+  exportDataViews: withDefaults(exportDataViews, { file: 'data-views.json' }),
+  importDataViews: withDefaults(importDataViews, { file: 'data-views.json' }),
+  exportDetectionRules: withDefaults(exportDetectionRules, {
+    file: 'detection-rules.json',
+  }),
+  importDetectionRules: withDefaults(importDetectionRules, {
+    file: 'detection-rules.json',
+  }),
+  exportSettings: withDefaults(exportSettings, { file: 'settings.json' }),
+  importSettings: withDefaults(importSettings, { file: 'settings.json' }),
+  exportFleet: withDefaults(exportFleet, { file: 'fleet.json' }),
+  importFleet: withDefaults(importFleet, { file: 'fleet.json' }),
+  KibobError,
+};
+
+// The command line without an option, and its value when it takes one
+function withoutOption(args, name, takesValue = true) {
+  return args.filter(
+    (arg, i) =>
+      arg !== name &&
+      !(takesValue && args[i - 1] === name) &&
+      !arg.startsWith(`${name}=`)
+  );
+}
+
+/* Run kibob in a child process and record its exit code and time in run,
+ * along with the result and errors it sends over the IPC channel.
+ */
+async function runChild(args, options, run) {
+  const started = Date.now();
+  try {
+    const child = spawn(process.execPath, [__filename, ...args], {
+      ...options,
+      stdio: ['inherit', 'inherit', 'inherit', 'ipc'],
+    });
+    child.on('message', (message) => {
+      if (message.result) {
+        run.result = message.result;
+        run.types = message.result.types || {};
+      }
+      if (message.error) run.errors.push(message.error);
+    });
+    const [code] = await once(child, 'exit');
+    run.exitCode = code;
   } catch (err) {
-    logger.error(err);
+    run.exitCode = exitCodes.failure;
+    run.errors.push(err.message);
+  }
+  run.seconds = (Date.now() - started) / 1000;
+  return run;
+}
+
+/* Run the command line once per workspace named by --workspace, "all" or
+ * a comma separated list of names from workspaces.json. Each run happens in
+ * the workspace's directory, so it finds its own remotes.json, kibob.json
+ * and lockfile, and gets the workspace's remote unless --remote is given.
+ */
+async function runWorkspaces(names, args, output) {
+  const workspaces = JSON.parse(await fs.readFile('workspaces.json', 'utf8'));
+  const selected = names === 'all' ? Object.keys(workspaces) : names.split(',');
+  const rest = withoutOption(args, '--workspace');
+
+  // a failed workspace does not stop the others, its errors are collected
+  // and reported along with the summary
+  const summary = [];
+  for (const name of selected) {
+    const workspace = workspaces[name];
+    const run = { workspace: name, exitCode: 0, types: {}, errors: [] };
+    if (!workspace) {
+      run.exitCode = exitCodes.failure;
+      run.errors.push(`Workspace '${name}' is not defined in workspaces.json`);
+      run.seconds = 0;
+      summary.push(run);
+      continue;
+    }
+    const remote =
+      workspace.remote && !rest.includes('--remote')
+        ? ['--remote', workspace.remote]
+        : [];
+    logger.info(`Workspace ${name}: ${workspace.dir}`);
+    summary.push(
+      await runChild([...rest, ...remote], { cwd: workspace.dir }, run)
+    );
+  }
+  reportRuns(summary, output, 'workspace');
+}
+
+/* Run the command line against every remote in remotes.json, --concurrency
+ * at a time, e.g. to deploy one bundle to a fleet of customer clusters. The
+ * runs take the lock on the checkout for each write to a shared file like
+ * the lockfile, and their results are summarized like those of workspaces.
+ */
+async function runRemotes(args, pre) {
+  const command = pre._[0];
+  if (isPullCommand(command)) {
+    throw new Error(
+      `${command} cannot run with --all-remotes, every remote would write ` +
+        'over the same files'
+    );
+  }
+  const remotes = JSON.parse(await fs.readFile('remotes.json', 'utf8'));
+  const names = Object.keys(remotes);
+  const rest = [
+    ...withoutOption(withoutOption(args, '--all-remotes', false), '--remote'),
+    '--lock-per-write',
+  ];
+
+  const summary = await mapLimit(pre.concurrency, names, (name) => {
+    const run = { remote: name, exitCode: 0, types: {}, errors: [] };
+    logger.info(`Remote ${name}: ${remotes[name].url}`);
+    return runChild([...rest, '--remote', name], {}, run);
+  });
+  reportRuns(summary, pre.output, 'remote');
+}
+
+/* Exit with the code of the runs that failed, partial failure when some
+ * succeeded, and print the summary and each failed run's errors.
+ */
+function reportRuns(summary, output, key) {
+  const failed = summary.filter((run) => run.exitCode);
+  if (failed.length > 0) {
+    const all = failed.length === summary.length;
+    fail(all ? failed[0].exitCode : exitCodes.partial);
+  }
+  if (output === 'json') {
+    console.log(JSON.stringify(summary, null, 2));
+    return;
+  }
+  printRuns(summary, key);
+  for (const run of failed) {
+    for (const error of run.errors) console.error(`${run[key]}: ${error}`);
+    if (run.errors.length === 0) {
+      console.error(`${run[key]}: exited with ${run.exitCode}`);
+    }
+  }
+}
+
+// Print a row per workspace or remote and type imported, with counts and timing
+function printRuns(summary, key) {
+  const rows = R.chain((run) => {
+    const status = run.exitCode ? `failed (${run.exitCode})` : 'ok';
+    const common = { ...run, status, seconds: run.seconds.toFixed(1) };
+    const types = Object.entries(run.types);
+    if (types.length === 0) {
+      const none = { type: '-', created: '-', updated: '-', failed: '-' };
+      return [{ ...common, ...none }];
+    }
+    return types.map(([type, counts]) => ({ ...common, type, ...counts }));
+  }, summary);
+  const columns = [key, 'type', 'created', 'updated', 'failed'];
+  printTable([...columns, 'status', 'seconds'], rows);
+}
+
+if (require.main === module) {
+  const args = process.argv.slice(2);
+  const pre = require('yargs/yargs')(args)
+    .option('workspace', { type: 'string' })
+    .option('all-remotes', { type: 'boolean' })
+    .option('concurrency', { type: 'number', default: 4 })
+    .option('lock-timeout', { type: 'number', default: 60 })
+    .option('output', { type: 'string' })
+    .help(false)
+    .version(false).argv;
+  const { workspace, output } = pre;
+  // the channel to runWorkspaces must not keep a workspace's command alive
+  if (process.channel) process.channel.unref();
+  if (workspace) {
+    runWorkspaces(workspace, args, output).catch((err) => {
+      fail();
+      logger.error(err.message);
+    });
+  } else if (pre.allRemotes) {
+    runRemotes(args, pre).catch((err) => {
+      fail();
+      logger.error(err.message);
+    });
+  } else {
+    cli.config(configDefaults()).env('KIBOB').parse();
   }
 }
@@ -0,0 +1,65 @@
+const R = require('ramda');
+const fs = require('fs').promises;
+const { resolveInside, safeSegment } = require('./paths.js');
+const state = require('./state.js');
+
+/* Where the markdown text of an expanded object lives: the params of a
+ * markdown visualization, or of each markdown panel stored by value in a
+ * dashboard. Returns an array of { path, name }, name being unique per object.
+ */
+function markdownPaths(obj) {
+  if (obj.type === 'visualization') {
+    const visType = R.path(['attributes', 'visState', 'type'], obj);
+    if (visType !== 'markdown') return [];
+    return [
+      { path: ['attributes', 'visState', 'params', 'markdown'], name: 'vis' },
+    ];
+  }
+
+  const panels = R.path(['attributes', 'panelsJSON'], obj);
+  if (obj.type !== 'dashboard' || !Array.isArray(panels)) return [];
+  const savedVis = ['embeddableConfig', 'savedVis'];
+  return R.chain((i) => {
+    if (R.path([...savedVis, 'type'], panels[i]) !== 'markdown') return [];
+    const params = ['attributes', 'panelsJSON', i, ...savedVis, 'params'];
+    return [
+      {
+        path: [...params, 'markdown'],
+        name: `panel-${safeSegment(panels[i].panelIndex || i)}`,
+      },
+    ];
+  }, R.range(0, panels.length));
+}
+
+/* Move the markdown text of an object into .md files under
+ * `${dir}/${assetDir}`, leaving a `file:` path in its place.
+ */
+async function extractMarkdown(obj, dir, assetDir) {
+  for (const { path, name } of markdownPaths(obj)) {
+    const text = R.path(path, obj);
+    if (typeof text !== 'string' || text.startsWith('file:')) continue;
+    const file = `${assetDir}/${name}.md`;
+    await fs.mkdir(resolveInside(dir, assetDir), { recursive: true });
+    await state.writeAtomic(resolveInside(dir, file), text);
+    obj = R.assocPath(path, `file:${file}`, obj);
+  }
+  return obj;
+}
+
+/* Read extracted .md files back into the object, relative to dir and only
+ * from inside it. read takes that relative path and defaults to reading the
+ * file from disk; diff passes one that reads it from a git ref instead.
+ */
+async function inlineMarkdown(obj, dir, read) {
+  const readFile = read || ((file) => fs.readFile(`${dir}/${file}`, 'utf8'));
+  for (const { path } of markdownPaths(obj)) {
+    const value = R.path(path, obj);
+    if (typeof value !== 'string' || !value.startsWith('file:')) continue;
+    const file = value.slice('file:'.length);
+    resolveInside(dir, file);
+    obj = R.assocPath(path, await readFile(file), obj);
+  }
+  return obj;
+}
+
+module.exports = { extractMarkdown, inlineMarkdown };
@@ -0,0 +1,24 @@
+const path = require('path');
+const { KibobError } = require('./errors.js');
+
+/* Make text from a saved object, like an id or type, safe to use as a single
+ * file name: no separators, and no leading dots that could make it . or ..
+ */
+function safeSegment(text) {
+  const safe = String(text)
+    .replace(/[^\w.-]+/g, '_')
+    .replace(/^\.+/, '_');
+  return safe || '_';
+}
+
+// Resolve a path from a file relative to dir, refusing one that leaves it
+function resolveInside(dir, file) {
+  const root = path.resolve(dir);
+  const resolved = path.resolve(root, file);
+  if (resolved !== root && !resolved.startsWith(root + path.sep)) {
+    throw new KibobError('UNSAFE_PATH', `${file} is outside ${dir}`);
+  }
+  return resolved;
+}
+
+module.exports = { resolveInside, safeSegment };
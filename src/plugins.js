@@ -0,0 +1,47 @@
+const path = require('path');
+const { KibobError } = require('./errors.js');
+
+const pluginsDir = 'plugins';
+
+/* Load plugins/<name>.js from the working directory. A plugin is a module
+ * exporting pull(obj), applied to objects on their way to disk, and push(obj)
+ * to undo it on their way to Kibana. Either may be left out, so a plugin that
+ * scrubs fields only needs pull.
+ */
+function loadPlugin(name) {
+  const file = path.resolve(pluginsDir, name);
+  let plugin;
+  try {
+    plugin = require(file);
+  } catch (err) {
+    if (err.code !== 'MODULE_NOT_FOUND' || !err.message.includes(file)) {
+      throw err;
+    }
+    throw new KibobError('NOT_FOUND', `No plugin ${pluginsDir}/${name}.js`);
+  }
+  if (typeof plugin.pull !== 'function' && typeof plugin.push !== 'function') {
+    throw new KibobError(
+      'INVALID_PLUGIN',
+      `${pluginsDir}/${name}.js exports neither pull nor push`
+    );
+  }
+  return plugin;
+}
+
+// Apply the named plugins' pull functions to an object, in order
+function pull(names, obj) {
+  return (names || []).reduce((pulled, name) => {
+    const plugin = loadPlugin(name);
+    return plugin.pull ? plugin.pull(pulled) : pulled;
+  }, obj);
+}
+
+// Apply the named plugins' push functions to an object, in reverse order
+function push(names, obj) {
+  return [...(names || [])].reverse().reduce((pushed, name) => {
+    const plugin = loadPlugin(name);
+    return plugin.push ? plugin.push(pushed) : pushed;
+  }, obj);
+}
+
+module.exports = { loadPlugin, pluginsDir, pull, push };
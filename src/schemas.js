@@ -0,0 +1,163 @@
+/* JSON Schemas for the attributes of common saved object types, after the
+ * saved object mappings in Kibana. Attributes Kibana stores as JSON strings
+ * may also be expanded, see transforms.js, so they take either form.
+ * Only the parts of JSON Schema used here are supported: type, enum,
+ * required, properties, additionalProperties and items.
+ */
+
+const searchSourceMeta = {
+  type: 'object',
+  additionalProperties: false,
+  properties: { searchSourceJSON: { type: ['string', 'object'] } },
+};
+
+const refreshInterval = {
+  type: 'object',
+  properties: {
+    pause: { type: 'boolean' },
+    value: { type: 'number' },
+  },
+};
+
+const schemas = {
+  dashboard: {
+    type: 'object',
+    required: ['title'],
+    additionalProperties: false,
+    properties: {
+      title: { type: 'string' },
+      description: { type: 'string' },
+      hits: { type: 'number' },
+      panelsJSON: { type: ['string', 'array'] },
+      optionsJSON: { type: ['string', 'object'] },
+      version: { type: 'number' },
+      timeRestore: { type: 'boolean' },
+      timeFrom: { type: 'string' },
+      timeTo: { type: 'string' },
+      refreshInterval,
+      controlGroupInput: { type: 'object' },
+      kibanaSavedObjectMeta: searchSourceMeta,
+    },
+  },
+  lens: {
+    type: 'object',
+    required: ['title', 'state'],
+    additionalProperties: false,
+    properties: {
+      title: { type: 'string' },
+      description: { type: 'string' },
+      visualizationType: { type: ['string', 'null'] },
+      state: { type: 'object' },
+      expression: { type: ['string', 'null'] },
+    },
+  },
+  'index-pattern': {
+    type: 'object',
+    required: ['title'],
+    additionalProperties: false,
+    properties: {
+      title: { type: 'string' },
+      name: { type: 'string' },
+      timeFieldName: { type: 'string' },
+      fields: { type: ['string', 'array'] },
+      fieldFormatMap: { type: ['string', 'object'] },
+      fieldAttrs: { type: ['string', 'object'] },
+      runtimeFieldMap: { type: ['string', 'object'] },
+      sourceFilters: { type: ['string', 'array'] },
+      typeMeta: { type: ['string', 'object'] },
+      type: { type: 'string' },
+      intervalName: { type: 'string' },
+      allowNoIndex: { type: 'boolean' },
+      allowHidden: { type: 'boolean' },
+    },
+  },
+  search: {
+    type: 'object',
+    required: ['title'],
+    additionalProperties: false,
+    properties: {
+      title: { type: 'string' },
+      description: { type: 'string' },
+      hits: { type: 'number' },
+      version: { type: 'number' },
+      columns: { type: 'array', items: { type: 'string' } },
+      sort: { type: 'array' },
+      grid: { type: 'object' },
+      hideChart: { type: 'boolean' },
+      isTextBasedQuery: { type: 'boolean' },
+      usesAdHocDataView: { type: 'boolean' },
+      timeRestore: { type: 'boolean' },
+      timeRange: { type: 'object' },
+      refreshInterval,
+      rowHeight: { type: 'number' },
+      headerRowHeight: { type: 'number' },
+      rowsPerPage: { type: 'number' },
+      sampleSize: { type: 'number' },
+      breakdownField: { type: 'string' },
+      viewMode: {
+        type: 'string',
+        enum: ['documents', 'patterns', 'aggregated'],
+      },
+      hideAggregatedPreview: { type: 'boolean' },
+      density: { type: 'string' },
+      visContext: { type: 'object' },
+      kibanaSavedObjectMeta: searchSourceMeta,
+    },
+  },
+};
+
+const placeholder = /^\{\{\s*[\w.-]+\s*\}\}$/;
+
+function typeOf(value) {
+  if (value === null) return 'null';
+  if (Array.isArray(value)) return 'array';
+  return typeof value;
+}
+
+/* Check a value against a schema, returning a problem for each mismatch.
+ * A {{ name }} placeholder stands in for a value of any type.
+ */
+function checkSchema(schema, value, path) {
+  if (typeof value === 'string' && placeholder.test(value)) return [];
+  const types = [].concat(schema.type || []);
+  const actual = typeOf(value);
+  if (types.length > 0 && !types.includes(actual)) {
+    return [`${path}: expected ${types.join(' or ')}, got ${actual}`];
+  }
+  if (schema.enum && !schema.enum.includes(value)) {
+    return [`${path}: expected one of ${schema.enum.join(', ')}`];
+  }
+
+  const problems = [];
+  if (actual === 'object' && schema.properties) {
+    for (const key of schema.required || []) {
+      if (!(key in value)) problems.push(`${path}.${key}: missing`);
+    }
+    for (const [key, item] of Object.entries(value)) {
+      if (schema.properties[key]) {
+        problems.push(
+          ...checkSchema(schema.properties[key], item, `${path}.${key}`)
+        );
+      } else if (schema.additionalProperties === false) {
+        problems.push(`${path}.${key}: unknown field`);
+      }
+    }
+  }
+  if (actual === 'array' && schema.items) {
+    value.forEach((item, i) => {
+      problems.push(...checkSchema(schema.items, item, `${path}.${i}`));
+    });
+  }
+  return problems;
+}
+
+/* Problems with a saved object's attributes, none for types without a schema
+ * or for a file that extends a shared one and has no attributes of its own.
+ */
+function validateAttributes(obj) {
+  const schema = schemas[obj.type];
+  if (!schema || (obj.extends && !obj.attributes)) return [];
+  return checkSchema(schema, obj.attributes, 'attributes');
+}
+
+module.exports = { checkSchema, schemas, validateAttributes };
@@ -0,0 +1,284 @@
+const fs = require('fs');
+const os = require('os');
+const path = require('path');
+const { KibobError } = require('./errors.js');
+
+const fsp = fs.promises;
+
+// Where kibob keeps what is local to a checkout and not for version control
+const stateDir = '.kibob';
+const lockFile = path.join(stateDir, 'lock');
+const lastPullFile = path.join(stateDir, 'last-pull.json');
+const backupsDir = path.join(stateDir, 'backups');
+const devDir = path.join(stateDir, 'dev');
+
+const sleep = (ms) => new Promise((resolve) => setTimeout(resolve, ms));
+
+// A temporary name next to a file, so renaming it over the file is atomic
+function tempFile(file) {
+  const name = `.${path.basename(file)}.${process.pid}.tmp`;
+  return path.join(path.dirname(file), name);
+}
+
+/* Write a file through a temporary file and a rename, so other processes
+ * and a crash halfway through never see it half written.
+ */
+async function writeAtomic(file, data) {
+  const temp = tempFile(file);
+  try {
+    await fsp.writeFile(temp, data);
+    await fsp.rename(temp, file);
+  } catch (err) {
+    await fsp.unlink(temp).catch(() => {});
+    throw err;
+  }
+}
+
+// Create the state directory, ignored by git wherever it is
+async function makeStateDir() {
+  await fsp.mkdir(stateDir, { recursive: true });
+  await fsp
+    .writeFile(path.join(stateDir, '.gitignore'), '*\n', { flag: 'wx' })
+    .catch((err) => {
+      if (err.code !== 'EEXIST') throw err;
+    });
+}
+
+// Who holds the lock, null while it is being written
+async function lockHolder() {
+  try {
+    return JSON.parse(await fsp.readFile(lockFile, 'utf8'));
+  } catch (err) {
+    if (err.code === 'ENOENT' || err instanceof SyntaxError) return null;
+    throw err;
+  }
+}
+
+// Whether the process holding a lock is gone, only known on the same host
+function isStale(holder) {
+  if (holder.host !== os.hostname()) return false;
+  try {
+    process.kill(holder.pid, 0);
+    return false;
+  } catch (err) {
+    return err.code === 'ESRCH';
+  }
+}
+
+function removeLock() {
+  try {
+    fs.unlinkSync(lockFile);
+  } catch (err) {
+    // already gone
+  }
+}
+
+// Remove the lock if this process holds it, not one taken since by another
+function releaseLock() {
+  let current;
+  try {
+    current = JSON.parse(fs.readFileSync(lockFile, 'utf8'));
+  } catch (err) {
+    return;
+  }
+  if (current.pid === process.pid && current.host === os.hostname()) {
+    removeLock();
+  }
+}
+
+let releaseOnExit = false;
+
+/* Take the lock on the state directory until released or the process exits,
+ * waiting up to timeout seconds for another kibob to finish with it. A lock
+ * left by a process that was killed on this host is taken over.
+ */
+async function acquireLock(command, timeout) {
+  await makeStateDir();
+  const holder = {
+    pid: process.pid,
+    host: os.hostname(),
+    command,
+    started: new Date().toISOString(),
+  };
+  const deadline = Date.now() + timeout * 1000;
+  for (;;) {
+    try {
+      await fsp.writeFile(lockFile, JSON.stringify(holder) + '\n', {
+        flag: 'wx',
+      });
+      if (!releaseOnExit) process.on('exit', releaseLock);
+      releaseOnExit = true;
+      return;
+    } catch (err) {
+      if (err.code !== 'EEXIST') throw err;
+    }
+    const current = await lockHolder();
+    if (current && isStale(current)) {
+      removeLock();
+      continue;
+    }
+    if (Date.now() >= deadline) {
+      const by = current
+        ? ` by \`${current.command}\` (pid ${current.pid} on ${current.host})`
+        : '';
+      throw new KibobError(
+        'LOCKED',
+        `${lockFile} is held${by}, remove it if that is no longer running`,
+        { holder: current }
+      );
+    }
+    await sleep(500);
+  }
+}
+
+/* Hold the lock only while fn runs, for commands like watch that run until
+ * stopped and would otherwise keep every other kibob waiting.
+ */
+async function withLock(command, timeout, fn) {
+  await acquireLock(command, timeout);
+  try {
+    return await fn();
+  } finally {
+    releaseLock();
+  }
+}
+
+async function readLastPull() {
+  try {
+    return JSON.parse(await fsp.readFile(lastPullFile, 'utf8'));
+  } catch (err) {
+    if (err.code === 'ENOENT') return {};
+    throw err;
+  }
+}
+
+// Record when objects were last pulled from a Kibana space, and how many
+async function recordPull(target, count) {
+  await makeStateDir();
+  const pulls = await readLastPull();
+  pulls[target] = { time: new Date().toISOString(), count };
+  await writeAtomic(lastPullFile, JSON.stringify(pulls, null, 2) + '\n');
+}
+
+// The objects `dev push` made in a scratch space, in .kibob/dev/<space>.json
+const devMappingFile = (space) => path.join(devDir, `${space}.json`);
+
+// The dev mapping of a space, null if nothing was pushed to it from here
+async function readDevMapping(space) {
+  try {
+    return JSON.parse(await fsp.readFile(devMappingFile(space), 'utf8'));
+  } catch (err) {
+    if (err.code === 'ENOENT') return null;
+    throw err;
+  }
+}
+
+async function writeDevMapping(space, mapping) {
+  await makeStateDir();
+  await fsp.mkdir(devDir, { recursive: true });
+  const text = JSON.stringify(mapping, null, 2) + '\n';
+  await writeAtomic(devMappingFile(space), text);
+}
+
+// Remove the dev mapping of a space, returning whether there was one
+async function removeDevMapping(space) {
+  const existed = (await readDevMapping(space)) !== null;
+  await fsp.rm(devMappingFile(space), { force: true });
+  return existed;
+}
+
+// One backup per run, named for the time it started
+let backupName;
+
+/* Copy files into a backup in .kibob/backups/<time>/ before they are
+ * overwritten, at the same paths they have in the working directory, or
+ * under outside/ for files outside it. The backup's index.json maps each copy
+ * to the file it came from. Returns the backup and the number of files.
+ */
+async function backupFiles(files) {
+  if (files.length === 0) return { count: 0 };
+  await makeStateDir();
+  backupName = backupName || new Date().toISOString().replace(/[:.]/g, '-');
+  const dir = path.join(backupsDir, backupName);
+  const indexFile = path.join(dir, 'index.json');
+  let index = {};
+  try {
+    index = JSON.parse(await fsp.readFile(indexFile, 'utf8'));
+  } catch (err) {
+    if (err.code !== 'ENOENT') throw err;
+  }
+
+  let count = 0;
+  for (const file of files) {
+    try {
+      if (!(await fsp.stat(file)).isFile()) continue;
+    } catch (err) {
+      if (err.code === 'ENOENT') continue;
+      throw err;
+    }
+    const relative = path.relative('.', file);
+    const outside = relative.startsWith('..') || path.isAbsolute(relative);
+    const parent = path.basename(path.dirname(path.resolve(file)));
+    const copy = outside
+      ? path.join('outside', parent, path.basename(file))
+      : relative;
+    // a file written more than once in a run keeps its first copy
+    if (index[copy]) continue;
+    await fsp.mkdir(path.dirname(path.join(dir, copy)), { recursive: true });
+    await fsp.copyFile(file, path.join(dir, copy));
+    index[copy] = relative;
+    count++;
+  }
+  if (count > 0) {
+    await writeAtomic(indexFile, JSON.stringify(index, null, 2) + '\n');
+  }
+  return { backup: dir, count };
+}
+
+// Copy the files of a backup, the latest if none is named, back into place
+async function restoreBackup(name) {
+  if (!name) {
+    let names = [];
+    try {
+      names = (await fsp.readdir(backupsDir)).sort();
+    } catch (err) {
+      if (err.code !== 'ENOENT') throw err;
+    }
+    if (names.length === 0) {
+      throw new KibobError('NOT_FOUND', `No backups in ${backupsDir}`);
+    }
+    name = names[names.length - 1];
+  }
+  const dir = path.join(backupsDir, name);
+  let index;
+  try {
+    index = JSON.parse(await fsp.readFile(path.join(dir, 'index.json')));
+  } catch (err) {
+    if (err.code !== 'ENOENT') throw err;
+    throw new KibobError('NOT_FOUND', `No backup ${dir}`);
+  }
+  const files = [];
+  for (const [copy, file] of Object.entries(index)) {
+    await fsp.mkdir(path.dirname(file), { recursive: true });
+    await fsp.copyFile(path.join(dir, copy), file);
+    files.push(file);
+  }
+  return { backup: dir, files };
+}
+
+module.exports = {
+  acquireLock,
+  backupFiles,
+  devMappingFile,
+  lockHolder,
+  readDevMapping,
+  recordPull,
+  releaseLock,
+  removeDevMapping,
+  restoreBackup,
+  stateDir,
+  tempFile,
+  withLock,
+  writeAtomic,
+  writeDevMapping,
+};
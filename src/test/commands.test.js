@@ -0,0 +1,221 @@
+/* Integration tests of the commands that work on a directory or on more than
+ * one Kibana, against MockKibana holding the objects of
+ * fixtures/saved_objects.ndjson, in a temporary working directory.
+ */
+const assert = require('assert');
+const fs = require('fs').promises;
+const os = require('os');
+const path = require('path');
+const { afterEach, beforeEach, test } = require('node:test');
+const kibob = require('../kibob.js');
+const { MockKibana, readFixture } = require('./mock-kibana.js');
+
+const fixture = readFixture('saved_objects.ndjson');
+const key = (obj) => `${obj.type}/${obj.id}`;
+
+let kibana;
+let url;
+
+beforeEach(async () => {
+  kibana = new MockKibana({ objects: fixture });
+  url = await kibana.start();
+  process.chdir(await fs.mkdtemp(path.join(os.tmpdir(), 'kibob-')));
+});
+
+afterEach(() => kibana.stop());
+
+const readObjectFile = async (dir, file) =>
+  JSON.parse(await fs.readFile(`${dir}/${file}`, 'utf8'));
+
+// The file in dir holding an object, by type and id
+async function fileOf(dir, type, id) {
+  for (const file of await fs.readdir(dir)) {
+    if (path.extname(file) !== '.json') continue;
+    const obj = await readObjectFile(dir, file);
+    if (obj.type === type && obj.id === id) return file;
+  }
+}
+
+// Unbundle the fixture into a directory, as export and unbundle would
+async function unbundleFixture(dir = 'objects') {
+  await kibob.saveObjects({ file: 'export.ndjson' }, fixture);
+  await kibob.unbundle({ file: 'export.ndjson', dir });
+}
+
+// Change an object's title in Kibana, as someone would in the UI
+function retitle(type, id, title, space = 'default') {
+  const obj = kibana.objects(space).find((o) => key(o) === `${type}/${id}`);
+  kibana.save(space, { ...obj, attributes: { ...obj.attributes, title } });
+}
+
+// Resolve once check() is true, checking every 50ms for up to 5 seconds
+async function eventually(check) {
+  for (let waited = 0; waited < 5000; waited += 50) {
+    if (await check()) return;
+    await new Promise((resolve) => setTimeout(resolve, 50));
+  }
+  assert.fail('timed out waiting for the change');
+}
+
+test('clones objects between remotes and into a mapped space', async () => {
+  const other = new MockKibana();
+  const target = await other.start();
+  try {
+    process.env.KIBOB_TEST_API_KEY = 'secret';
+    const remote = (remoteUrl) => ({
+      url: remoteUrl,
+      apiKeyEnv: 'KIBOB_TEST_API_KEY',
+      spaces: { ops: 'operations' },
+    });
+    await fs.writeFile(
+      'remotes.json',
+      JSON.stringify({ prod: remote(url), staging: remote(target) })
+    );
+
+    const result = await kibob.clone({ from: 'prod', to: 'staging' });
+    assert.strictEqual(result.successCount, fixture.length);
+    assert.deepStrictEqual(
+      other.objects().map(key).sort(),
+      fixture.map(key).sort()
+    );
+
+    await kibob.clone({ from: 'prod', to: 'staging', mapSpace: 'default:ops' });
+    assert.deepStrictEqual(
+      other.objects('operations').map(key).sort(),
+      fixture.map(key).sort()
+    );
+  } finally {
+    delete process.env.KIBOB_TEST_API_KEY;
+    await other.stop();
+  }
+});
+
+test('renames an object and the references to it', async () => {
+  await unbundleFixture();
+  const before = await fileOf('objects', 'visualization', 'requests-by-status');
+
+  const { rewritten } = await kibob.rename({
+    dir: 'objects',
+    type: 'visualization',
+    id: 'requests-by-status',
+    newId: 'status-pie',
+    title: 'Status pie',
+  });
+  assert.strictEqual(rewritten.length, 2);
+  const after = await fileOf('objects', 'visualization', 'status-pie');
+  assert.notStrictEqual(after, before);
+  await assert.rejects(fs.access(`objects/${before}`));
+  const renamed = await readObjectFile('objects', after);
+  assert.strictEqual(renamed.attributes.title, 'Status pie');
+
+  const dashboard = await readObjectFile(
+    'objects',
+    await fileOf('objects', 'dashboard', 'web-traffic')
+  );
+  assert.strictEqual(dashboard.references[0].id, 'status-pie');
+});
+
+test('rolls back to a snapshot, pruning objects made since', async () => {
+  const { file, count } = await kibob.snapshot({ url });
+  assert.strictEqual(count, fixture.length);
+  assert.ok(file.startsWith('snapshots/'));
+
+  // only the types in the snapshot are pruned, and it has no tags
+  retitle('dashboard', 'web-traffic', 'Changed in Kibana');
+  kibana.save('default', {
+    type: 'dashboard',
+    id: 'new-dashboard',
+    attributes: { title: 'New' },
+    references: [],
+  });
+  kibana.save('default', {
+    type: 'tag',
+    id: 'new-tag',
+    attributes: { name: 'New', color: '#ffffff' },
+    references: [],
+  });
+  const result = await kibob.rollback({ url, prune: true, yes: true });
+  assert.deepStrictEqual(result.pruned, ['dashboard/new-dashboard']);
+  const dashboard = kibana.objects().find((obj) => obj.type === 'dashboard');
+  assert.strictEqual(dashboard.attributes.title, 'Web traffic');
+  assert.deepStrictEqual(
+    kibana.objects().map(key).sort(),
+    [...fixture.map(key), 'tag/new-tag'].sort()
+  );
+});
+
+test('fails to roll back without a snapshot', async () => {
+  await fs.mkdir('snapshots');
+  await assert.rejects(kibob.rollback({ url }), { code: 'NOT_FOUND' });
+});
+
+test('watch imports saved files and pulls changes made in Kibana', async () => {
+  await unbundleFixture();
+  const file = await fileOf('objects', 'visualization', 'requests-by-status');
+  const watcher = await kibob.watch({ url, dir: 'objects', poll: 0.2 });
+  try {
+    const obj = await readObjectFile('objects', file);
+    obj.attributes.title = 'Edited in the editor';
+    await fs.writeFile(`objects/${file}`, JSON.stringify(obj, null, 2));
+    await eventually(() =>
+      kibana
+        .objects()
+        .some((o) => o.attributes.title === 'Edited in the editor')
+    );
+
+    retitle('dashboard', 'web-traffic', 'Edited in Kibana');
+    const dashboard = await fileOf('objects', 'dashboard', 'web-traffic');
+    await eventually(async () => {
+      const text = await fs.readFile(`objects/${dashboard}`, 'utf8');
+      return text.includes('Edited in Kibana');
+    });
+  } finally {
+    watcher.close();
+  }
+});
+
+test('audit reports which objects match the repository', async () => {
+  await unbundleFixture();
+  retitle('dashboard', 'web-traffic', 'Changed in Kibana');
+  kibana.save('default', {
+    type: 'tag',
+    id: 'unmanaged',
+    attributes: { name: 'Made in the UI', color: '#ffffff' },
+    references: [],
+    updated_by: 'u_ann',
+  });
+
+  const report = await kibob.audit({ url, dir: 'objects' });
+  assert.deepStrictEqual(report.summary, {
+    total: fixture.length + 1,
+    matches: fixture.length - 1,
+    modified: 1,
+    notInRepo: 1,
+    missingRemotely: 0,
+    managed: 0,
+  });
+  const tag = report.objects.find((obj) => obj.id === 'unmanaged');
+  assert.strictEqual(tag.updatedBy, 'u_ann');
+});
+
+test('validate --prune deletes files left behind by a rename', async () => {
+  await unbundleFixture();
+  const file = await fileOf('objects', 'dashboard', 'web-traffic');
+  await fs.copyFile(`objects/${file}`, 'objects/old-name.json');
+
+  const found = await kibob.validate({ dir: 'objects' });
+  assert.strictEqual(found.valid, false);
+  const orphans = found.problems.filter(({ problem }) =>
+    problem.startsWith('orphaned')
+  );
+  assert.deepStrictEqual(
+    orphans.map((problem) => problem.file),
+    ['old-name.json']
+  );
+
+  const pruned = await kibob.validate({ dir: 'objects', prune: true });
+  assert.strictEqual(pruned.valid, true);
+  assert.deepStrictEqual(pruned.pruned, ['old-name.json']);
+  await assert.rejects(fs.access('objects/old-name.json'));
+  await fs.access(`objects/${file}`);
+});
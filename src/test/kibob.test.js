@@ -0,0 +1,300 @@
+/* Integration tests of the library entry points against MockKibana, run
+ * with `npm test`. Each test gets a fresh Kibana holding the objects of
+ * fixtures/saved_objects.ndjson, and a temporary working directory.
+ */
+const assert = require('assert');
+const fs = require('fs').promises;
+const os = require('os');
+const path = require('path');
+const { afterEach, beforeEach, test } = require('node:test');
+const kibob = require('../kibob.js');
+const { MockKibana, readFixture } = require('./mock-kibana.js');
+
+const fixture = readFixture('saved_objects.ndjson');
+const key = (obj) => `${obj.type}/${obj.id}`;
+
+let kibana;
+let url;
+
+beforeEach(async () => {
+  kibana = new MockKibana({ objects: fixture });
+  url = await kibana.start();
+  process.chdir(await fs.mkdtemp(path.join(os.tmpdir(), 'kibob-')));
+});
+
+afterEach(() => kibana.stop());
+
+const readFile = async (file) =>
+  (await fs.readFile(file, 'utf8'))
+    .split('\n')
+    .filter((line) => line.trim())
+    .map((line) => JSON.parse(line));
+
+test('exports every object from Kibana', async () => {
+  const objects = await kibob.exportObjects({ url });
+  assert.deepStrictEqual(objects.map(key).sort(), fixture.map(key).sort());
+});
+
+test('exports more objects than the find API can page through', async () => {
+  for (let i = 0; i < 10500; i++) {
+    kibana.save('default', {
+      type: 'tag',
+      id: `tag-${i}`,
+      attributes: { name: `Tag ${i}`, color: '#000000' },
+      references: [],
+    });
+  }
+  const objects = await kibob.exportObjects({ url, types: ['tag'] });
+  assert.strictEqual(objects.length, 10500);
+});
+
+test('unbundles and bundles an export without losing objects', async () => {
+  await kibob.saveObjects({ file: 'export.ndjson' }, fixture);
+  await kibob.unbundle({ file: 'export.ndjson', dir: 'objects' });
+  const result = await kibob.bundle({ dir: 'objects', file: 'bundle.ndjson' });
+
+  assert.strictEqual(result.count, fixture.length);
+  const bundled = (await readFile('bundle.ndjson')).map(key);
+  assert.deepStrictEqual(bundled, [
+    'index-pattern/logs-web',
+    'visualization/requests-by-status',
+    'dashboard/web-traffic',
+  ]);
+});
+
+test('imports objects in reference order and locks them', async () => {
+  await kibob.saveObjects({ file: 'export.ndjson' }, fixture);
+  const empty = new MockKibana();
+  const target = await empty.start();
+  try {
+    const result = await kibob.importObjects({
+      url: target,
+      file: 'export.ndjson',
+    });
+    assert.strictEqual(result.successCount, fixture.length);
+    assert.deepStrictEqual(empty.objects().map(key), [
+      'index-pattern/logs-web',
+      'visualization/requests-by-status',
+      'dashboard/web-traffic',
+    ]);
+    const lock = JSON.parse(await fs.readFile('kibob.lock', 'utf8'));
+    assert.strictEqual(Object.keys(Object.values(lock)[0]).length, 3);
+  } finally {
+    await empty.stop();
+  }
+});
+
+test('imports an export from the Kibana UI with its summary line', async () => {
+  const summary = {
+    excludedObjects: [],
+    excludedObjectsCount: 0,
+    exportedCount: fixture.length,
+    missingRefCount: 0,
+    missingReferences: [],
+  };
+  const lines = [...fixture, summary].map((obj) => JSON.stringify(obj));
+  await fs.writeFile('export.ndjson', lines.join('\n') + '\n');
+
+  // every type, like the CLI, so the summary line is not filtered by type
+  const result = await kibob.importObjects({
+    url,
+    file: 'export.ndjson',
+    overwrite: true,
+    types: null,
+  });
+  assert.strictEqual(result.successCount, fixture.length);
+  const lock = JSON.parse(await fs.readFile('kibob.lock', 'utf8'));
+  assert.deepStrictEqual(
+    Object.keys(Object.values(lock)[0]).sort(),
+    fixture.map(key).sort()
+  );
+});
+
+test('remaps ids without splitting ES|QL queries into lines', async () => {
+  const esql = 'FROM logs-web | STATS count = COUNT(*) BY status | LIMIT 10';
+  const lens = {
+    type: 'lens',
+    id: 'status-counts',
+    attributes: { title: 'Status counts', state: { query: { esql } } },
+    references: [{ type: 'index-pattern', id: 'logs-web', name: 'source' }],
+  };
+  await kibob.saveObjects({ file: 'export.ndjson' }, [lens]);
+  await fs.writeFile('remap.json', JSON.stringify({ 'logs-web': 'logs' }));
+  await kibob.importObjects({
+    url,
+    file: 'export.ndjson',
+    remap: 'remap.json',
+    types: ['lens'],
+  });
+
+  const imported = kibana.objects().find((obj) => obj.type === 'lens');
+  assert.strictEqual(imported.attributes.state.query.esql, esql);
+  assert.strictEqual(imported.references[0].id, 'logs');
+});
+
+test('refuses to import over objects changed in Kibana', async () => {
+  await kibob.saveObjects({ file: 'export.ndjson' }, fixture);
+  await kibob.importObjects({ url, file: 'export.ndjson', overwrite: true });
+  const [first] = kibana.objects();
+  kibana.save('default', {
+    ...first,
+    attributes: { ...first.attributes, title: 'Changed in Kibana' },
+  });
+
+  await assert.rejects(
+    kibob.importObjects({ url, file: 'export.ndjson', overwrite: true }),
+    { code: 'DRIFTED', drifted: [key(first)] }
+  );
+  const [current] = kibana.objects();
+  assert.strictEqual(current.attributes.title, 'Changed in Kibana');
+});
+
+test('status lists the objects changed in Kibana', async () => {
+  await kibob.saveObjects({ file: 'export.ndjson' }, fixture);
+  await kibob.unbundle({ file: 'export.ndjson', dir: 'objects' });
+  assert.deepStrictEqual(
+    (await kibob.status({ url, dir: 'objects' })).modified,
+    []
+  );
+
+  const [first] = kibana.objects();
+  kibana.save('default', {
+    ...first,
+    attributes: { ...first.attributes, title: 'Changed in Kibana' },
+  });
+  const status = await kibob.status({ url, dir: 'objects' });
+  assert.deepStrictEqual(status.modified.map(key), [key(first)]);
+});
+
+test('status only compares objects of the given types', async () => {
+  await kibob.saveObjects({ file: 'export.ndjson' }, fixture);
+  await kibob.unbundle({ file: 'export.ndjson', dir: 'objects' });
+  const status = await kibob.status({
+    url,
+    dir: 'objects',
+    types: ['dashboard'],
+  });
+  assert.deepStrictEqual(status.missingRemotely, []);
+  assert.deepStrictEqual(status.missingLocally, []);
+});
+
+test('status ignores moved dashboard panels unless exact', async () => {
+  await kibob.saveObjects({ file: 'export.ndjson' }, fixture);
+  await kibob.unbundle({ file: 'export.ndjson', dir: 'objects' });
+
+  const dashboard = kibana.objects().find((obj) => obj.type === 'dashboard');
+  const panels = JSON.parse(dashboard.attributes.panelsJSON);
+  panels[0].gridData = { ...panels[0].gridData, x: 24, w: 12 };
+  kibana.save('default', {
+    ...dashboard,
+    attributes: { ...dashboard.attributes, panelsJSON: JSON.stringify(panels) },
+  });
+  const status = await kibob.status({ url, dir: 'objects' });
+  assert.deepStrictEqual(status.modified, []);
+  const exact = await kibob.status({ url, dir: 'objects', exact: true });
+  assert.deepStrictEqual(exact.modified.map(key), [key(dashboard)]);
+});
+
+test('throws Kibana errors to the caller', async () => {
+  kibana.failWith(401, { statusCode: 401, error: 'Unauthorized' });
+  await assert.rejects(kibob.exportObjects({ url }), {
+    name: 'KibobError',
+    code: 'AUTH_FAILED',
+    status: 401,
+  });
+  assert.strictEqual(process.exitCode, undefined);
+});
+
+test('scrubs secrets on pull and fills them in on push', async () => {
+  const secret = 'https://hooks.example.com/T0KEN';
+  const name = 'REQUESTS_BY_STATUS_VISSTATE_PARAMS_URL';
+  const vis = fixture.find((obj) => obj.type === 'visualization');
+  const visState = JSON.parse(vis.attributes.visState);
+  visState.params.url = secret;
+  kibana.save('default', {
+    ...vis,
+    attributes: { ...vis.attributes, visState: JSON.stringify(visState) },
+  });
+  const secrets = ['attributes.visState.params.url'];
+  const config = { transforms: { visualization: { secrets } } };
+  await fs.writeFile('kibob.json', JSON.stringify(config));
+
+  const objects = await kibob.exportObjects({ url });
+  await kibob.saveObjects({ file: 'export.ndjson' }, objects);
+  await kibob.unbundle({ file: 'export.ndjson', dir: 'objects' });
+  const files = await fs.readdir('objects');
+  const text = (
+    await Promise.all(files.map((file) => fs.readFile(`objects/${file}`)))
+  ).join('');
+  assert.ok(!text.includes(secret));
+  assert.ok(text.includes(`\${${name}}`));
+  const status = await kibob.status({ url, dir: 'objects' });
+  assert.deepStrictEqual(status.modified, []);
+
+  await assert.rejects(kibob.bundle({ dir: 'objects', file: 'b.ndjson' }), {
+    code: 'MISSING_SECRET',
+    secret: name,
+  });
+  await fs.writeFile('secrets.json', JSON.stringify({ [name]: secret }));
+  await kibob.bundle({
+    dir: 'objects',
+    file: 'b.ndjson',
+    secrets: 'secrets.json',
+  });
+  const bundled = (await readFile('b.ndjson')).find((obj) => obj.id === vis.id);
+  const { params } = JSON.parse(bundled.attributes.visState);
+  assert.strictEqual(params.url, secret);
+});
+
+test('dev push imports suffixed copies into a space of their own', async () => {
+  await kibob.saveObjects({ file: 'export.ndjson' }, fixture);
+  await kibob.unbundle({ file: 'export.ndjson', dir: 'objects' });
+  const pushed = await kibob.devPush({ url, dir: 'objects', user: 'Ann Lee' });
+
+  assert.deepStrictEqual(
+    kibana.objects('dev-ann-lee').map(key).sort(),
+    fixture.map((obj) => `${key(obj)}-dev-ann-lee`).sort()
+  );
+  const dashboard = kibana
+    .objects('dev-ann-lee')
+    .find((obj) => obj.type === 'dashboard');
+  assert.strictEqual(dashboard.attributes.title, 'Web traffic [dev-ann-lee]');
+  assert.strictEqual(
+    dashboard.references[0].id,
+    'requests-by-status-dev-ann-lee'
+  );
+  assert.ok(kibana.objects().every((obj) => !obj.id.endsWith('-dev-ann-lee')));
+  const link = pushed.find((obj) => obj.type === 'dashboard').link;
+  assert.strictEqual(
+    link,
+    `${url}/s/dev-ann-lee/app/dashboards#/view/web-traffic-dev-ann-lee`
+  );
+  const mapping = JSON.parse(
+    await fs.readFile('.kibob/dev/dev-ann-lee.json', 'utf8')
+  );
+  assert.strictEqual(mapping.objects.length, fixture.length);
+});
+
+test('dev clean deletes the objects pushed or the whole space', async () => {
+  await kibob.saveObjects({ file: 'export.ndjson' }, fixture);
+  await kibob.unbundle({ file: 'export.ndjson', dir: 'objects' });
+  await kibob.devPush({ url, dir: 'objects', user: 'ann' });
+  const own = { ...fixture[0], id: 'made-by-hand' };
+  kibana.save('dev-ann', own);
+
+  const cleaned = await kibob.devClean({
+    url,
+    user: 'ann',
+    objectsOnly: true,
+    yes: true,
+  });
+  assert.strictEqual(cleaned.deleted.length, fixture.length);
+  assert.deepStrictEqual(kibana.objects('dev-ann').map(key), [key(own)]);
+  await assert.rejects(fs.access('.kibob/dev/dev-ann.json'));
+
+  await kibob.devClean({ url, user: 'ann' });
+  assert.ok(kibana.spaces['dev-ann'], 'kept without --yes or a terminal');
+  const removed = await kibob.devClean({ url, user: 'ann', yes: true });
+  assert.strictEqual(removed.spaceDeleted, true);
+  assert.strictEqual(kibana.spaces['dev-ann'], undefined);
+});
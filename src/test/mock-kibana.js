@@ -0,0 +1,443 @@
+/* A stand-in for the Kibana saved objects API, to test kibob without a
+ * cluster. Saved objects are kept in memory per space, and the endpoints
+ * kibob calls are served from them: status, _find, _export, _import,
+ * _bulk_get and delete, and the spaces API. So are the APIs of what is not
+ * a saved object, like roles, connectors and Fleet policies, each kept as a
+ * collection of items per space. Every request is recorded in `requests`.
+ */
+const fs = require('fs');
+const http = require('http');
+const path = require('path');
+
+const key = (obj) => `${obj.type}/${obj.id}`;
+
+// Read the saved objects of a recorded export in test/fixtures
+function readFixture(name) {
+  return fs
+    .readFileSync(path.join(__dirname, 'fixtures', name), 'utf8')
+    .split('\n')
+    .filter((line) => line.trim())
+    .map((line) => JSON.parse(line));
+}
+
+// The ndjson file of a multipart/form-data import request
+function importedFile(req, body) {
+  const boundary = /boundary=(.+)$/.exec(req.headers['content-type'])[1];
+  const part = body
+    .split(`--${boundary}`)
+    .find((text) => text.includes('filename='));
+  return part.slice(part.indexOf('\r\n\r\n') + 4, part.lastIndexOf('\r\n'));
+}
+
+class MockKibana {
+  constructor({ version = '8.12.0', objects = [] } = {}) {
+    this.version = version;
+    this.spaces = { default: new Map() };
+    for (const obj of objects) this.save('default', obj);
+    this.requests = [];
+    this.failure = null;
+    this.collections = {};
+    this.settings = {};
+  }
+
+  // Answer every request but /api/status with status and body from now on
+  failWith(status, body = { statusCode: status }) {
+    this.failure = { status, body };
+  }
+
+  save(space, obj) {
+    this.spaces[space] = this.spaces[space] || new Map();
+    this.spaces[space].set(key(obj), obj);
+  }
+
+  objects(space = 'default') {
+    return [...(this.spaces[space] || new Map()).values()];
+  }
+
+  /* The items of a collection, like 'roles' or 'connectors', by id. Roles
+   * are not in a space, so they are always in the default one.
+   */
+  items(name, space = 'default') {
+    const collections = (this.collections[space] =
+      this.collections[space] || {});
+    collections[name] = collections[name] || new Map();
+    return collections[name];
+  }
+
+  // Listen on a free port, resolving to the URL to pass as `url`
+  async start() {
+    this.server = http.createServer((req, res) => {
+      let body = '';
+      req.setEncoding('utf8');
+      req.on('data', (chunk) => (body += chunk));
+      req.on('end', () => this.handle(req, res, body));
+    });
+    await new Promise((resolve) => this.server.listen(0, '127.0.0.1', resolve));
+    return `http://127.0.0.1:${this.server.address().port}`;
+  }
+
+  async stop() {
+    this.server.closeAllConnections();
+    await new Promise((resolve) => this.server.close(resolve));
+  }
+
+  handle(req, res, body) {
+    const url = new URL(req.url, 'http://kibana');
+    const [, space = 'default', api] = /^(?:\/s\/([^/]+))?(\/.*)$/.exec(
+      url.pathname
+    );
+    this.requests.push({ method: req.method, path: url.pathname, body });
+    const send = (status, data, type = 'application/json') => {
+      res.writeHead(status, { 'Content-Type': type });
+      res.end(typeof data === 'string' ? data : JSON.stringify(data));
+    };
+    if (this.failure && api !== '/api/status') {
+      return send(this.failure.status, this.failure.body);
+    }
+
+    const store = this.spaces[space] || new Map();
+    const route = `${req.method} ${api}`;
+    if (route === 'GET /api/status') {
+      return send(200, { version: { number: this.version } });
+    }
+    if (route === 'GET /api/saved_objects/_find') {
+      const types = url.searchParams.getAll('type');
+      const page = Number(url.searchParams.get('page') || 1);
+      const perPage = Number(url.searchParams.get('per_page') || 20);
+      // like Elasticsearch's index.max_result_window
+      if (page * perPage > 10000) {
+        return send(400, { statusCode: 400, error: 'Bad Request' });
+      }
+      const found = [...store.values()].filter(
+        (obj) => types.length === 0 || types.includes(obj.type)
+      );
+      return send(200, {
+        page,
+        per_page: perPage,
+        total: found.length,
+        saved_objects: found.slice((page - 1) * perPage, page * perPage),
+      });
+    }
+    if (route === 'POST /api/saved_objects/_export') {
+      const request = JSON.parse(body);
+      const search = (request.search || '').replace(/\*/g, '').toLowerCase();
+      const matches = (obj) =>
+        request.type.includes(obj.type) &&
+        String(obj.attributes.title || '')
+          .toLowerCase()
+          .includes(search);
+      const exported = request.type
+        ? [...store.values()].filter(matches)
+        : request.objects
+            .map((obj) => store.get(key(obj)))
+            .filter((obj) => obj);
+      const summary = { exportedCount: exported.length, missingRefCount: 0 };
+      const lines = [
+        ...exported,
+        ...(request.excludeExportDetails ? [] : [summary]),
+      ].map((obj) => JSON.stringify(obj));
+      return send(200, lines.join('\n'), 'application/ndjson');
+    }
+    if (route === 'POST /api/saved_objects/_bulk_get') {
+      const wanted = JSON.parse(body);
+      if (wanted.some((obj) => !obj.type)) {
+        return send(400, { statusCode: 400, error: 'Bad Request' });
+      }
+      return send(200, {
+        saved_objects: wanted.map(
+          (obj) =>
+            store.get(key(obj)) || {
+              ...obj,
+              error: { statusCode: 404, error: 'Not Found' },
+            }
+        ),
+      });
+    }
+    if (route === 'POST /api/saved_objects/_import') {
+      const overwrite = url.searchParams.get('overwrite') === 'true';
+      const objects = importedFile(req, body)
+        .split('\n')
+        .filter((line) => line.trim())
+        .map((line) => JSON.parse(line));
+      const successResults = [];
+      const errors = [];
+      for (const obj of objects) {
+        const exists = store.has(key(obj));
+        if (exists && !overwrite) {
+          const error = { type: 'conflict' };
+          errors.push({ type: obj.type, id: obj.id, error });
+          continue;
+        }
+        this.save(space, obj);
+        successResults.push({ type: obj.type, id: obj.id, overwrite: exists });
+      }
+      return send(200, {
+        success: errors.length === 0,
+        successCount: successResults.length,
+        successResults,
+        errors,
+      });
+    }
+    if (route === 'GET /api/spaces/space') {
+      return send(
+        200,
+        Object.keys(this.spaces).map((id) => ({ id, name: id }))
+      );
+    }
+    if (route === 'POST /api/spaces/space') {
+      const { id } = JSON.parse(body);
+      if (this.spaces[id]) return send(409, { statusCode: 409 });
+      this.spaces[id] = new Map();
+      return send(200, JSON.parse(body));
+    }
+    const spaceId = /^\/api\/spaces\/space\/([^/]+)$/.exec(api);
+    if (spaceId && !this.spaces[spaceId[1]]) {
+      return send(404, { statusCode: 404, error: 'Not Found' });
+    }
+    if (spaceId && req.method === 'GET') {
+      return send(200, { id: spaceId[1], name: spaceId[1] });
+    }
+    if (spaceId && req.method === 'DELETE') {
+      delete this.spaces[spaceId[1]];
+      return send(204, '');
+    }
+    const object = /^\/api\/saved_objects\/([^/_][^/]*)\/([^/]+)$/.exec(api);
+    if (req.method === 'DELETE' && object) {
+      const [, type, id] = object;
+      if (!store.delete(`${type}/${decodeURIComponent(id)}`)) {
+        return send(404, { statusCode: 404, error: 'Not Found' });
+      }
+      return send(200, {});
+    }
+    if (this.handleResource(req, url, space, api, body, send) !== false) {
+      return;
+    }
+    send(404, { statusCode: 404, error: 'Not Found', message: route });
+  }
+
+  // Serve the APIs of what is not a saved object, false for any other route
+  handleResource(req, url, space, api, body, send) {
+    const json = () => JSON.parse(body);
+    const param = (name, fallback) =>
+      Number(url.searchParams.get(name) || fallback);
+    const notFound = () => send(404, { statusCode: 404, error: 'Not Found' });
+    const page = (items, perPageName) => {
+      const number = param('page', 1);
+      const perPage = param(perPageName, 20);
+      const slice = items.slice((number - 1) * perPage, number * perPage);
+      return { number, perPage, total: items.length, slice };
+    };
+    const match = (pattern) => {
+      const found = pattern.exec(api);
+      return (
+        found &&
+        found.slice(1).map((part) => part && decodeURIComponent(part))
+      );
+    };
+    const route = `${req.method} ${api}`;
+
+    if (api === '/api/cases/configure') {
+      const configs = this.items('cases', space);
+      if (req.method === 'GET') return send(200, [...configs.values()]);
+      const config = { ...json(), id: `config-${json().owner}`, version: 'v1' };
+      configs.set(config.id, config);
+      return send(200, config);
+    }
+    let found = match(/^\/api\/cases\/configure\/([^/]+)$/);
+    if (found && req.method === 'PATCH') {
+      const configs = this.items('cases', space);
+      const current = configs.get(found[0]);
+      if (!current) return notFound();
+      if (json().version !== current.version) {
+        return send(409, { statusCode: 409, error: 'Conflict' });
+      }
+      const version = `v${Number(current.version.slice(1)) + 1}`;
+      configs.set(current.id, { ...current, ...json(), version });
+      return send(200, configs.get(current.id));
+    }
+
+    if (route === 'GET /api/security/role') {
+      return send(200, [...this.items('roles').values()]);
+    }
+    found = match(/^\/api\/security\/role\/([^/]+)$/);
+    if (found && req.method === 'PUT') {
+      this.items('roles').set(found[0], { name: found[0], ...json() });
+      return send(204, '');
+    }
+
+    const slos = this.items('slos', space);
+    if (route === 'GET /api/observability/slos') {
+      const { number, perPage, total, slice } = page(
+        [...slos.values()],
+        'perPage'
+      );
+      return send(200, { page: number, perPage, total, results: slice });
+    }
+    if (route === 'POST /api/observability/slos') {
+      slos.set(json().id, { ...json(), revision: 1 });
+      return send(200, { id: json().id });
+    }
+    found = match(/^\/api\/observability\/slos\/([^/]+)$/);
+    if (found) {
+      const current = slos.get(found[0]);
+      if (!current) return notFound();
+      if (req.method === 'GET') return send(200, current);
+      const revision = current.revision + 1;
+      slos.set(found[0], { ...current, ...json(), revision });
+      return send(200, slos.get(found[0]));
+    }
+
+    // Kibana never returns the secrets of a connector
+    const connectors = this.items('connectors', space);
+    const withoutSecrets = ({ secrets, ...connector }) => connector;
+    if (route === 'GET /api/actions/connectors') {
+      return send(200, [...connectors.values()].map(withoutSecrets));
+    }
+    found = match(/^\/api\/actions\/connector\/([^/]+)$/);
+    if (found) {
+      const [id] = found;
+      const current = connectors.get(id);
+      if (req.method === 'GET') {
+        return current ? send(200, withoutSecrets(current)) : notFound();
+      }
+      if (req.method === 'PUT' && !current) return notFound();
+      connectors.set(id, { ...current, ...json(), id });
+      return send(200, withoutSecrets(connectors.get(id)));
+    }
+
+    const rules = this.items('rules', space);
+    if (route === 'GET /api/alerting/rules/_find') {
+      const { number, perPage, total, slice } = page(
+        [...rules.values()],
+        'per_page'
+      );
+      return send(200, { page: number, per_page: perPage, total, data: slice });
+    }
+    found = match(/^\/api\/alerting\/rule\/([^/]+?)(?:\/(_enable|_disable))?$/);
+    if (found) {
+      const [id, toggle] = found;
+      const current = rules.get(id);
+      if (req.method === 'POST' && !toggle) {
+        if (current) return send(409, { statusCode: 409, error: 'Conflict' });
+        rules.set(id, { enabled: true, ...json(), id });
+        return send(200, rules.get(id));
+      }
+      if (!current) return notFound();
+      if (req.method === 'GET') return send(200, current);
+      if (toggle) {
+        rules.set(id, { ...current, enabled: toggle === '_enable' });
+        return send(204, '');
+      }
+      rules.set(id, { ...current, ...json() });
+      return send(200, rules.get(id));
+    }
+
+    const dataViews = this.items('data_views', space);
+    if (route === 'GET /api/data_views') {
+      const list = [...dataViews.values()].map(({ id, title, name }) => ({
+        id,
+        title,
+        name,
+      }));
+      return send(200, { data_view: list });
+    }
+    if (route === 'POST /api/data_views/data_view') {
+      const dataView = json().data_view;
+      if (dataViews.has(dataView.id)) {
+        return send(409, { statusCode: 409, error: 'Conflict' });
+      }
+      dataViews.set(dataView.id, { ...dataView, fields: {} });
+      return send(200, { data_view: dataViews.get(dataView.id) });
+    }
+    found = match(/^\/api\/data_views\/data_view\/([^/]+)$/);
+    if (found) {
+      const current = dataViews.get(found[0]);
+      if (!current) return notFound();
+      if (req.method === 'GET') return send(200, { data_view: current });
+      dataViews.set(found[0], { ...current, ...json().data_view });
+      return send(200, { data_view: dataViews.get(found[0]) });
+    }
+
+    const detection = this.items('detection_rules', space);
+    if (route === 'GET /api/detection_engine/rules/_find') {
+      const { number, perPage, total, slice } = page(
+        [...detection.values()],
+        'per_page'
+      );
+      return send(200, { page: number, perPage, total, data: slice });
+    }
+    if (route === 'POST /api/detection_engine/rules/_export') {
+      const lines = json()
+        .objects.map(({ rule_id }) => detection.get(rule_id))
+        .filter((rule) => rule)
+        .map((rule) => JSON.stringify(rule));
+      return send(200, lines.join('\n'), 'application/ndjson');
+    }
+    if (route === 'POST /api/detection_engine/rules/_import') {
+      const items = importedFile(req, body)
+        .split('\n')
+        .filter((line) => line.trim())
+        .map((line) => JSON.parse(line));
+      const imported = items.filter((item) => item.rule_id);
+      for (const rule of imported) detection.set(rule.rule_id, rule);
+      return send(200, {
+        success: true,
+        success_count: imported.length,
+        errors: [],
+      });
+    }
+
+    found = match(/^\/api\/fleet\/(\w+_policies)(?:\/([^/]+))?$/);
+    if (found) {
+      const [kind, id] = found;
+      const policies = this.items(kind, space);
+      if (req.method === 'GET' && !id) {
+        const { number, perPage, total, slice } = page(
+          [...policies.values()],
+          'perPage'
+        );
+        return send(200, { items: slice, total, page: number, perPage });
+      }
+      if (req.method === 'POST') {
+        policies.set(json().id, json());
+        return send(200, { item: json() });
+      }
+      const current = policies.get(id);
+      if (!current) return notFound();
+      if (req.method === 'GET') return send(200, { item: current });
+      policies.set(id, { ...current, ...json(), id });
+      return send(200, { item: policies.get(id) });
+    }
+
+    if (api === '/api/kibana/settings') {
+      const settings = (this.settings[space] = this.settings[space] || {});
+      if (req.method === 'GET') return send(200, { settings });
+      for (const [key, value] of Object.entries(json().changes)) {
+        if (value === null) delete settings[key];
+        else settings[key] = { userValue: value };
+      }
+      return send(200, { settings });
+    }
+
+    const shortUrls = this.items('short_urls', space);
+    found = match(/^\/api\/short_url\/_slug\/([^/]+)$/);
+    if (found && req.method === 'GET') {
+      const shortUrl = shortUrls.get(found[0]);
+      return shortUrl ? send(200, shortUrl) : notFound();
+    }
+    if (route === 'POST /api/short_url') {
+      const { locatorId, params, slug } = json();
+      if (slug && shortUrls.has(slug)) {
+        return send(409, { statusCode: 409, error: 'Conflict' });
+      }
+      const created = slug || `slug-${shortUrls.size + 1}`;
+      const locator = { id: locatorId, version: '8.12.0', state: params };
+      const shortUrl = { id: created, slug: created, locator };
+      shortUrls.set(created, shortUrl);
+      return send(200, shortUrl);
+    }
+    return false;
+  }
+}
+
+module.exports = { MockKibana, readFixture };
@@ -0,0 +1,344 @@
+/* Integration tests of the commands for what is not a saved object, against
+ * MockKibana: each resource is exported from one Kibana into a file and
+ * imported from it into another, in a temporary working directory.
+ */
+const assert = require('assert');
+const fs = require('fs').promises;
+const os = require('os');
+const path = require('path');
+const { afterEach, beforeEach, test } = require('node:test');
+const kibob = require('../kibob.js');
+const { MockKibana } = require('./mock-kibana.js');
+
+let source;
+let target;
+let from;
+let to;
+
+beforeEach(async () => {
+  source = new MockKibana();
+  target = new MockKibana();
+  from = await source.start();
+  to = await target.start();
+  process.chdir(await fs.mkdtemp(path.join(os.tmpdir(), 'kibob-')));
+});
+
+afterEach(async () => {
+  await source.stop();
+  await target.stop();
+});
+
+const readJson = async (file) => JSON.parse(await fs.readFile(file, 'utf8'));
+
+test('copies case settings, updating those the space has', async () => {
+  const cases = source.items('cases');
+  cases.set('c1', {
+    id: 'c1',
+    version: 'v3',
+    owner: 'securitySolution',
+    closure_type: 'close-by-pushing',
+    connector: { id: 'none', name: 'none', type: '.none', fields: null },
+    created_at: '2024-05-01T12:00:00.000Z',
+  });
+  await kibob.exportCases({ url: from });
+  const [saved] = await readJson('cases.json');
+  assert.strictEqual(saved.owner, 'securitySolution');
+  assert.strictEqual(saved.id, undefined);
+  assert.strictEqual(saved.created_at, undefined);
+
+  assert.deepStrictEqual(await kibob.importCases({ url: to }), [
+    { owner: 'securitySolution', action: 'created' },
+  ]);
+  assert.deepStrictEqual(await kibob.importCases({ url: to }), [
+    { owner: 'securitySolution', action: 'updated' },
+  ]);
+  const [config] = target.items('cases').values();
+  assert.strictEqual(config.closure_type, 'close-by-pushing');
+  assert.strictEqual(config.version, 'v2');
+});
+
+test('copies roles, leaving reserved ones and other spaces out', async () => {
+  const roles = source.items('roles');
+  const kibana = (spaces) => [{ base: ['read'], feature: {}, spaces }];
+  roles.set('superuser', {
+    name: 'superuser',
+    metadata: { _reserved: true },
+    kibana: kibana(['*']),
+  });
+  roles.set('ops', { name: 'ops', elasticsearch: {}, kibana: kibana(['ops']) });
+  roles.set('web', { name: 'web', elasticsearch: {}, kibana: kibana(['web']) });
+  await kibob.exportRoles({ url: from, space: 'ops' });
+  const saved = await readJson('roles.json');
+  assert.deepStrictEqual(saved.map((role) => role.name), ['ops']);
+
+  const results = await kibob.importRoles({ url: to });
+  assert.deepStrictEqual(results, [{ name: 'ops', action: 'saved' }]);
+  const role = target.items('roles').get('ops');
+  assert.deepStrictEqual(role.kibana, kibana(['ops']));
+});
+
+test('copies SLOs page by page without their generated state', async () => {
+  const slos = source.items('slos');
+  for (let i = 0; i < 150; i++) {
+    const id = `slo-${String(i).padStart(3, '0')}`;
+    slos.set(id, {
+      id,
+      name: `SLO ${i}`,
+      indicator: { type: 'sli.kql.custom', params: {} },
+      objective: { target: 0.99 },
+      revision: 4,
+      summary: { status: 'HEALTHY' },
+    });
+  }
+  const { count } = await kibob.exportSlos({ url: from });
+  assert.strictEqual(count, 150);
+  const saved = await readJson('slos.json');
+  assert.strictEqual(saved[0].summary, undefined);
+  assert.strictEqual(saved[0].revision, undefined);
+
+  target.items('slos').set('slo-000', { id: 'slo-000', revision: 1 });
+  const results = await kibob.importSlos({ url: to });
+  assert.strictEqual(results.length, 150);
+  assert.strictEqual(results[0].action, 'updated');
+  assert.strictEqual(results[1].action, 'created');
+  assert.strictEqual(target.items('slos').get('slo-000').name, 'SLO 0');
+});
+
+test('copies connectors with their secrets from the environment', async () => {
+  source.items('connectors').set('ops-slack', {
+    id: 'ops-slack',
+    name: 'Ops Slack',
+    connector_type_id: '.slack',
+    config: {},
+    is_preconfigured: false,
+  });
+  source.items('connectors').set('preconfigured', {
+    id: 'preconfigured',
+    name: 'From kibana.yml',
+    connector_type_id: '.server-log',
+    is_preconfigured: true,
+  });
+  await kibob.exportConnectors({ url: from });
+  const saved = await readJson('connectors.json');
+  assert.deepStrictEqual(saved, [
+    {
+      id: 'ops-slack',
+      name: 'Ops Slack',
+      connector_type_id: '.slack',
+      config: {},
+      secrets: { webhookUrl: '{{ OPS_SLACK_WEBHOOK_URL }}' },
+    },
+  ]);
+
+  delete process.env.OPS_SLACK_WEBHOOK_URL;
+  const missing = await kibob.importConnectors({ url: to });
+  assert.strictEqual(missing[0].action, 'failed');
+  assert.strictEqual(target.items('connectors').size, 0);
+
+  process.env.OPS_SLACK_WEBHOOK_URL = 'https://hooks.slack.com/T0/B0/x';
+  try {
+    const results = await kibob.importConnectors({ url: to });
+    assert.strictEqual(results[0].action, 'created');
+    const connector = target.items('connectors').get('ops-slack');
+    assert.deepStrictEqual(connector.secrets, {
+      webhookUrl: 'https://hooks.slack.com/T0/B0/x',
+    });
+  } finally {
+    delete process.env.OPS_SLACK_WEBHOOK_URL;
+  }
+});
+
+test('copies alerting rules and enables or disables them', async () => {
+  const rule = (id, fields) => ({
+    id,
+    name: id,
+    rule_type_id: '.es-query',
+    consumer: 'alerts',
+    schedule: { interval: '1m' },
+    params: {},
+    actions: [{ id: 'ops-slack', group: 'query matched', uuid: 'a1' }],
+    api_key_owner: 'elastic',
+    ...fields,
+  });
+  source.items('rules').set('errors', rule('errors', { enabled: false }));
+  source.items('rules').set('siem', rule('siem', { consumer: 'siem' }));
+  await kibob.exportRules({ url: from });
+  const saved = await readJson('rules.json');
+  assert.deepStrictEqual(saved.map((item) => item.id), ['errors']);
+  assert.strictEqual(saved[0].api_key_owner, undefined);
+  assert.strictEqual(saved[0].actions[0].uuid, undefined);
+
+  target.items('rules').set('errors', rule('errors', { enabled: true }));
+  const results = await kibob.importRules({ url: to });
+  assert.deepStrictEqual(results, [
+    { id: 'errors', name: 'errors', action: 'updated' },
+  ]);
+  assert.strictEqual(target.items('rules').get('errors').enabled, false);
+});
+
+test('copies data views with runtime scripts split into lines', async () => {
+  const script = "emit(doc['status'].value);\nemit(0);";
+  source.items('data_views').set('logs-web', {
+    id: 'logs-web',
+    title: 'logs-web-*',
+    name: 'Web logs',
+    timeFieldName: '@timestamp',
+    runtimeFieldMap: {
+      code: { type: 'long', script: { source: script } },
+    },
+    fields: { status: { type: 'number' } },
+  });
+  await kibob.exportDataViews({ url: from });
+  const [saved] = await readJson('data-views.json');
+  assert.strictEqual(saved.fields, undefined);
+  assert.ok(Array.isArray(saved.runtimeFieldMap.code.script.source));
+
+  const results = await kibob.importDataViews({ url: to });
+  assert.deepStrictEqual(results, [
+    { id: 'logs-web', name: 'Web logs', action: 'created' },
+  ]);
+  const created = target.items('data_views').get('logs-web');
+  assert.strictEqual(created.runtimeFieldMap.code.script.source, script);
+  const again = await kibob.importDataViews({ url: to });
+  assert.strictEqual(again[0].action, 'updated');
+});
+
+test('copies detection rules through the detection engine', async () => {
+  source.items('detection_rules').set('brute-force', {
+    rule_id: 'brute-force',
+    name: 'Brute force',
+    type: 'query',
+    query: 'event.outcome: failure',
+    updated_at: '2024-05-01T12:00:00.000Z',
+    revision: 3,
+  });
+  const { count } = await kibob.exportDetectionRules({ url: from });
+  assert.strictEqual(count, 1);
+  const saved = await readJson('detection-rules.json');
+  assert.strictEqual(saved[0].updated_at, undefined);
+  assert.strictEqual(saved[0].revision, undefined);
+
+  const results = await kibob.importDetectionRules({ url: to });
+  assert.deepStrictEqual(results, [
+    { rule_id: 'brute-force', name: 'Brute force', action: 'imported' },
+  ]);
+  const imported = target.items('detection_rules').get('brute-force');
+  assert.strictEqual(imported.query, 'event.outcome: failure');
+});
+
+test('copies Fleet policies with their secrets as placeholders', async () => {
+  source.items('agent_policies').set('fleet-server', {
+    id: 'fleet-server',
+    name: 'Fleet Server',
+    is_managed: true,
+  });
+  source.items('agent_policies').set('web', {
+    id: 'web',
+    name: 'Web servers',
+    namespace: 'default',
+    revision: 9,
+  });
+  source.items('package_policies').set('nginx', {
+    id: 'nginx',
+    name: 'nginx-1',
+    policy_id: 'web',
+    package: { name: 'nginx', title: 'Nginx', version: '1.20.0' },
+    vars: { password: { type: 'password', value: 'hunter2' } },
+    inputs: [{ type: 'logfile', compiled_input: {} }],
+  });
+  await kibob.exportFleet({ url: from });
+  const saved = await readJson('fleet.json');
+  assert.deepStrictEqual(saved.agentPolicies.map((policy) => policy.id), [
+    'web',
+  ]);
+  const [nginx] = saved.packagePolicies;
+  assert.strictEqual(nginx.vars.password.value, '{{ nginx.password }}');
+  assert.strictEqual(nginx.inputs[0].compiled_input, undefined);
+
+  await fs.writeFile(
+    'values.json',
+    JSON.stringify({ 'nginx.password': 'hunter2' })
+  );
+  const results = await kibob.importFleet({ url: to, values: 'values.json' });
+  assert.deepStrictEqual(
+    results.map((result) => [result.kind, result.id, result.action]),
+    [
+      ['agent', 'web', 'created'],
+      ['integration', 'nginx', 'created'],
+    ]
+  );
+  const policy = target.items('package_policies').get('nginx');
+  assert.strictEqual(policy.vars.password.value, 'hunter2');
+});
+
+test('copies the advanced settings changed from their defaults', async () => {
+  source.settings.default = {
+    buildNum: { userValue: 1234 },
+    'theme:darkMode': { userValue: true },
+    'csv:separator': { userValue: ';', isOverridden: true },
+    'dateFormat:tz': {},
+  };
+  await kibob.exportSettings({ url: from });
+  assert.deepStrictEqual(await readJson('settings.json'), {
+    'theme:darkMode': true,
+  });
+
+  target.settings.default = { 'dateFormat:tz': { userValue: 'UTC' } };
+  await fs.writeFile(
+    'settings.json',
+    JSON.stringify({ 'theme:darkMode': true, 'dateFormat:tz': null })
+  );
+  const { count } = await kibob.importSettings({ url: to });
+  assert.strictEqual(count, 2);
+  assert.deepStrictEqual(target.settings.default, {
+    'theme:darkMode': { userValue: true },
+  });
+});
+
+test('recreates linked short URLs and links to their new slugs', async () => {
+  const locator = {
+    id: 'DASHBOARD_APP_LOCATOR',
+    version: '8.12.0',
+    state: { dashboardId: 'web-traffic' },
+  };
+  source.items('short_urls').set('web-errors', {
+    id: 'web-errors',
+    slug: 'web-errors',
+    locator,
+  });
+  const link = '/goto/web-errors';
+  source.save('default', {
+    type: 'dashboard',
+    id: 'drilldowns',
+    attributes: { title: 'Drilldowns', description: link },
+    references: [],
+  });
+  const objects = await kibob.exportObjects({
+    url: from,
+    types: ['dashboard'],
+    shortUrls: 'short-urls.json',
+  });
+  assert.deepStrictEqual(await readJson('short-urls.json'), {
+    'web-errors': locator,
+  });
+
+  // another link holds the slug on the target, so Kibana picks a new one
+  target.items('short_urls').set('web-errors', {
+    id: 'web-errors',
+    slug: 'web-errors',
+    locator: { ...locator, state: { dashboardId: 'other' } },
+  });
+  await kibob.saveObjects({ file: 'export.ndjson' }, objects);
+  await kibob.importObjects({
+    url: to,
+    file: 'export.ndjson',
+    shortUrls: 'short-urls.json',
+    lock: false,
+  });
+  const [dashboard] = target.objects();
+  assert.strictEqual(dashboard.attributes.description, '/goto/slug-2');
+  assert.deepStrictEqual(
+    target.items('short_urls').get('slug-2').locator.state,
+    locator.state
+  );
+});
@@ -0,0 +1,455 @@
+const R = require('ramda');
+
+const searchSource = [
+  'attributes',
+  'kibanaSavedObjectMeta',
+  'searchSourceJSON',
+];
+
+// Attributes Kibana stores as JSON encoded strings, by saved object type
+const jsonStringFields = {
+  dashboard: [
+    ['attributes', 'panelsJSON'],
+    ['attributes', 'optionsJSON'],
+    searchSource,
+  ],
+  'index-pattern': [
+    ['attributes', 'fields'],
+    ['attributes', 'fieldFormatMap'],
+    ['attributes', 'fieldAttrs'],
+    ['attributes', 'runtimeFieldMap'],
+  ],
+  map: [
+    ['attributes', 'layerListJSON'],
+    ['attributes', 'mapStateJSON'],
+    ['attributes', 'uiStateJSON'],
+  ],
+  search: [searchSource],
+  visualization: [
+    ['attributes', 'visState'],
+    ['attributes', 'uiStateJSON'],
+    searchSource,
+  ],
+};
+
+// Multi-line visState params, by visualization type
+const multilineParams = {
+  metrics: ['markdown'],
+  timelion: ['expression'],
+  vega: ['spec'],
+};
+
+function visParamPaths(obj) {
+  const visType = R.path(['attributes', 'visState', 'type'], obj);
+  return (multilineParams[visType] || []).map((param) => [
+    'attributes',
+    'visState',
+    'params',
+    param,
+  ]);
+}
+
+// Apply fn to the value of every esql key, like a Lens or Discover query
+function mapEsql(fn, value) {
+  if (Array.isArray(value)) return value.map((item) => mapEsql(fn, item));
+  if (value && typeof value === 'object') {
+    return R.mapObjIndexed(
+      (item, key) => (key === 'esql' ? fn(item) : mapEsql(fn, item)),
+      value
+    );
+  }
+  return value;
+}
+
+/* Split an ES|QL query into lines: on its own line breaks, or before each
+ * pipe outside a string when it is all on one line. Kibana gets the lines
+ * back joined by line breaks, which ES|QL treats like any other space.
+ */
+function splitEsql(query) {
+  if (typeof query !== 'string') return query;
+  if (query.includes('\n')) return query.split('\n');
+
+  const lines = [];
+  let start = 0;
+  let quoted = false;
+  for (let i = 0; i < query.length; i++) {
+    if (query[i] === '"' && query[i - 1] !== '\\') quoted = !quoted;
+    if (query[i] === '|' && !quoted) {
+      lines.push(query.slice(start, i).trim());
+      start = i;
+    }
+  }
+  lines.push(query.slice(start).trim());
+  return lines.length > 1 ? lines : query;
+}
+
+// Split multi-line params like a vega spec, and ES|QL queries, into lines
+function splitMultiline(obj) {
+  const split = visParamPaths(obj).reduce((split, path) => {
+    const value = R.path(path, split);
+    if (typeof value !== 'string' || !value.includes('\n')) return split;
+    return R.assocPath(path, value.split('\n'), split);
+  }, obj);
+  return mapEsql(splitEsql, split);
+}
+
+// Reverse of splitMultiline, join arrays of lines back into strings
+function joinMultiline(obj) {
+  const joined = visParamPaths(obj).reduce((joined, path) => {
+    const value = R.path(path, joined);
+    if (!Array.isArray(value)) return joined;
+    return R.assocPath(path, value.join('\n'), joined);
+  }, obj);
+  const join = (query) => (Array.isArray(query) ? query.join('\n') : query);
+  return mapEsql(join, joined);
+}
+
+// Apply fn to the script of each runtime field of a data view
+function mapRuntimeScripts(fn, dataView) {
+  if (!dataView.runtimeFieldMap) return dataView;
+  const runtimeFieldMap = R.map(
+    (field) =>
+      field.script
+        ? R.assocPath(['script', 'source'], fn(field.script.source), field)
+        : field,
+    dataView.runtimeFieldMap
+  );
+  return { ...dataView, runtimeFieldMap };
+}
+
+// Split multi-line runtime field scripts of a data view into lines
+const splitRuntimeScripts = (dataView) =>
+  mapRuntimeScripts(
+    (source) =>
+      typeof source === 'string' && source.includes('\n')
+        ? source.split('\n')
+        : source,
+    dataView
+  );
+
+// Reverse of splitRuntimeScripts, join the lines back into scripts
+const joinRuntimeScripts = (dataView) =>
+  mapRuntimeScripts(
+    (source) => (Array.isArray(source) ? source.join('\n') : source),
+    dataView
+  );
+
+/* Sort a dashboard's panels by panelIndex and leave out their gridData, so
+ * moving or resizing panels, or Kibana saving them in another order, is not
+ * a change. Expects panelsJSON already expanded.
+ */
+function ignoreLayout(obj) {
+  const panels = R.path(['attributes', 'panelsJSON'], obj);
+  if (obj.type !== 'dashboard' || !Array.isArray(panels)) return obj;
+  const unplaced = R.sortBy(
+    (panel) => String(panel.panelIndex),
+    panels.map(R.omit(['gridData']))
+  );
+  return R.assocPath(['attributes', 'panelsJSON'], unplaced, obj);
+}
+
+/* Parse JSON encoded attributes into real objects, and unless multiline is
+ * false split multi-line visualization params into lines, for readable diffs.
+ */
+function expandJsonStrings(obj, { multiline = true } = {}) {
+  const fields = jsonStringFields[obj.type] || [];
+  const parsed = fields.reduce((expanded, path) => {
+    const value = R.path(path, expanded);
+    if (typeof value !== 'string') return expanded;
+    try {
+      return R.assocPath(path, JSON.parse(value), expanded);
+    } catch (err) {
+      return expanded;
+    }
+  }, obj);
+  return multiline ? splitMultiline(parsed) : parsed;
+}
+
+// Reverse of expandJsonStrings, encode the attributes back into strings
+function compactJsonStrings(obj) {
+  const joined = joinMultiline(obj);
+  return (jsonStringFields[obj.type] || []).reduce((compacted, path) => {
+    const value = R.path(path, compacted);
+    if (value === undefined || typeof value === 'string') return compacted;
+    return R.assocPath(path, JSON.stringify(value), compacted);
+  }, joined);
+}
+
+// Apply fn to every string, number and boolean value, at any depth
+function mapLeaves(fn, value) {
+  if (Array.isArray(value)) return value.map((item) => mapLeaves(fn, item));
+  if (value && typeof value === 'object') {
+    return R.map((item) => mapLeaves(fn, item), value);
+  }
+  return fn(value);
+}
+
+/* Apply fn(leaf, path) to every leaf of a saved object, like mapLeaves,
+ * including the leaves of its JSON encoded attributes. Those are encoded
+ * again only when fn changed one of their leaves, and nothing is split into
+ * lines, so the rest of the object is left as it was, down to where the
+ * lines of an ES|QL query break.
+ */
+function mapObjectLeaves(fn, obj) {
+  const encoded = jsonStringFields[obj.type] || [];
+  const walk = (value, path, decode) => {
+    if (Array.isArray(value)) {
+      return value.map((item, i) => walk(item, [...path, i], decode));
+    }
+    if (value && typeof value === 'object') {
+      return R.mapObjIndexed(
+        (item, key) => walk(item, [...path, key], decode),
+        value
+      );
+    }
+    if (decode && typeof value === 'string' && encoded.some(R.equals(path))) {
+      let parsed;
+      try {
+        parsed = JSON.parse(value);
+      } catch (err) {
+        return fn(value, path);
+      }
+      const mapped = walk(parsed, path, false);
+      return R.equals(mapped, parsed) ? value : JSON.stringify(mapped);
+    }
+    return fn(value, path);
+  };
+  return walk(obj, [], true);
+}
+
+/* Rewrite an object's id, its references, and any ids embedded in its
+ * attributes (including JSON encoded ones) using a map of old to new ids.
+ */
+function remapIds(obj, ids) {
+  return mapObjectLeaves(
+    (leaf) => (typeof leaf === 'string' && R.has(leaf, ids) ? ids[leaf] : leaf),
+    obj
+  );
+}
+
+const shortUrl = /\/goto\/([\w-]+)/g;
+
+// The slugs of the /goto/ short URLs an object links to, as in drilldowns
+function shortUrlSlugs(obj) {
+  const slugs = new Set();
+  mapLeaves((leaf) => {
+    if (typeof leaf !== 'string') return leaf;
+    for (const [, slug] of leaf.matchAll(shortUrl)) slugs.add(slug);
+    return leaf;
+  }, obj);
+  return [...slugs];
+}
+
+// Point /goto/ links at new short URL slugs, using a map of old to new slugs
+function rewriteSlugs(obj, slugs) {
+  return mapLeaves(
+    (leaf) =>
+      typeof leaf === 'string'
+        ? leaf.replace(shortUrl, (match, slug) =>
+            R.has(slug, slugs) ? `/goto/${slugs[slug]}` : match
+          )
+        : leaf,
+    obj
+  );
+}
+
+const placeholder = /\{\{\s*([\w.-]+)\s*\}\}/g;
+const wholePlaceholder = /^\{\{\s*([\w.-]+)\s*\}\}$/;
+
+/* Substitute {{ name }} placeholders in a string. A string that is only a
+ * placeholder takes the value as is, so numbers and booleans keep their type.
+ * Placeholders without a value are left alone.
+ */
+function fillString(value, values) {
+  const whole = value.match(wholePlaceholder);
+  if (whole && R.has(whole[1], values)) return values[whole[1]];
+  return value.replace(placeholder, (match, name) =>
+    R.has(name, values) ? String(values[name]) : match
+  );
+}
+
+// Fill {{ name }} placeholders anywhere in an object, including JSON strings
+function fillTemplate(obj, values) {
+  const fill = (leaf) =>
+    typeof leaf === 'string' ? fillString(leaf, values) : leaf;
+  return mapObjectLeaves(fill, obj);
+}
+
+// Names of the {{ name }} placeholders anywhere in an object
+function placeholderNames(obj) {
+  const names = [];
+  mapLeaves((leaf) => {
+    if (typeof leaf !== 'string') return leaf;
+    for (const match of leaf.matchAll(placeholder)) names.push(match[1]);
+    return leaf;
+  }, obj);
+  return R.uniq(names);
+}
+
+// Shortest string value templated outside of configured paths
+const templateMinLength = 8;
+
+/* Reverse of fillTemplate, turn attribute strings that exactly match a
+ * template value back into {{ name }} placeholders. With paths, dotted
+ * patterns like those of dropFields, only values at those paths are
+ * templated; without, only values of at least templateMinLength characters,
+ * so a short value does not turn unrelated fields into placeholders.
+ * Numbers and booleans are never templated.
+ */
+function templateObject(obj, values, paths = []) {
+  const names = new Map(
+    Object.keys(values)
+      .filter((name) => typeof values[name] === 'string')
+      .filter((name) =>
+        paths.length > 0
+          ? values[name] !== ''
+          : values[name].length >= templateMinLength
+      )
+      .map((name) => [values[name], `{{ ${name} }}`])
+  );
+  if (!obj.attributes || names.size === 0) return obj;
+
+  const atPath = (path) => paths.length === 0 || matchPaths(paths, path);
+  return mapObjectLeaves(
+    (value, path) =>
+      path[0] === 'attributes' && names.has(value) && atPath(path)
+        ? names.get(value)
+        : value,
+    obj
+  );
+}
+
+const secretPlaceholder = /^\$\{([A-Z0-9_]+)\}$/;
+
+// Upper snake case name of a secret, from the object's id and the field path
+const secretName = (id, path) =>
+  [id, ...(path[0] === 'attributes' ? path.slice(1) : path)]
+    .join('_')
+    .replace(/[^A-Za-z0-9]+/g, '_')
+    .replace(/^_+|_+$/g, '')
+    .toUpperCase();
+
+/* Replace the values at dotted path patterns, like those of dropFields but
+ * reaching into JSON encoded attributes too, with ${NAME} placeholders, so
+ * credentials in an object never reach the disk. NAME is the object's id and
+ * the path below attributes in upper snake case, like
+ * ${SLACK_HOOK_CONFIG_TOKEN}. Empty values are left alone.
+ */
+function scrubSecrets(obj, paths) {
+  if (paths.length === 0) return obj;
+  return mapObjectLeaves(
+    (value, path) =>
+      value !== null &&
+      value !== '' &&
+      !secretPlaceholder.test(value) &&
+      matchPaths(paths, path)
+        ? `\${${secretName(obj.id, path)}}`
+        : value,
+    obj
+  );
+}
+
+/* Reverse of scrubSecrets, fill the ${NAME} placeholders at the same paths
+ * with lookup(name).
+ */
+function injectSecrets(obj, paths, lookup) {
+  if (paths.length === 0) return obj;
+  return mapObjectLeaves((value, path) => {
+    const match = typeof value === 'string' && value.match(secretPlaceholder);
+    return match && matchPaths(paths, path) ? lookup(match[1]) : value;
+  }, obj);
+}
+
+// Names of the ${NAME} placeholders at the paths of an object
+function secretNames(obj, paths) {
+  const names = [];
+  injectSecrets(obj, paths, (name) => names.push(name));
+  return R.uniq(names);
+}
+
+const escapeRegExp = (text) => text.replace(/[.*+?^${}()|[\]\\]/g, '\\$&');
+
+// Whether a key matches one segment of a drop pattern, * matching anything
+function matchSegment(segment, key) {
+  const pattern = segment.split('*').map(escapeRegExp).join('.*');
+  return new RegExp(`^${pattern}$`).test(key);
+}
+
+// Whether a path of keys matches one of the dotted path patterns
+function matchPaths(patterns, path) {
+  return patterns.some((pattern) => {
+    const segments = pattern.split('.');
+    return (
+      segments.length === path.length &&
+      segments.every((segment, i) => matchSegment(segment, String(path[i])))
+    );
+  });
+}
+
+/* Remove the fields matching dotted path patterns like `updated_at`,
+ * `attributes.*_by` or `coreMigrationVersion`. Each segment matches one key
+ * and may use * as a wildcard. JSON encoded attributes are not looked into.
+ */
+function dropFields(obj, patterns) {
+  const drop = (value, [segment, ...rest]) => {
+    if (!value || typeof value !== 'object' || Array.isArray(value)) {
+      return value;
+    }
+    const kept = {};
+    for (const [key, child] of Object.entries(value)) {
+      if (!matchSegment(segment, key)) kept[key] = child;
+      else if (rest.length > 0) kept[key] = drop(child, rest);
+    }
+    return kept;
+  };
+  return patterns.reduce(
+    (dropped, pattern) => drop(dropped, pattern.split('.')),
+    obj
+  );
+}
+
+// Sort object keys at every depth, so key order never shows up in a diff
+function sortKeys(value) {
+  if (Array.isArray(value)) return value.map(sortKeys);
+  if (value && typeof value === 'object') {
+    return Object.keys(value)
+      .sort()
+      .reduce((sorted, key) => {
+        sorted[key] = sortKeys(value[key]);
+        return sorted;
+      }, {});
+  }
+  return value;
+}
+
+// Put an object in a stable form: keys sorted, references sorted by type+id
+function normalizeObject(obj) {
+  const sorted = sortKeys(obj);
+  if (!Array.isArray(sorted.references)) return sorted;
+  return {
+    ...sorted,
+    references: R.sortWith(
+      [R.ascend(R.prop('type')), R.ascend(R.prop('id'))],
+      sorted.references
+    ),
+  };
+}
+
+module.exports = {
+  compactJsonStrings,
+  dropFields,
+  expandJsonStrings,
+  fillTemplate,
+  ignoreLayout,
+  injectSecrets,
+  joinRuntimeScripts,
+  normalizeObject,
+  placeholderNames,
+  remapIds,
+  rewriteSlugs,
+  scrubSecrets,
+  secretNames,
+  shortUrlSlugs,
+  splitRuntimeScripts,
+  templateObject,
+};
@@ -0,0 +1,75 @@
+const R = require('ramda');
+const { validateAttributes } = require('./schemas.js');
+
+/* Check a set of saved object files for problems that would break an import:
+ * unparseable JSON, objects missing a type or id, attributes that do not
+ * match their type's schema, duplicate ids, and references to objects that
+ * are not in the set.
+ * Takes an array of { file, text } and returns an array of { file, problem }.
+ */
+function validateFiles(files) {
+  const problems = [];
+  const objects = [];
+
+  for (const { file, text } of files) {
+    try {
+      const obj = JSON.parse(text);
+      if (!obj.type || !obj.id) {
+        problems.push({ file, problem: 'missing type or id' });
+      } else {
+        objects.push({ file, obj });
+        for (const problem of validateAttributes(obj)) {
+          problems.push({ file, problem });
+        }
+      }
+    } catch (err) {
+      problems.push({ file, problem: `invalid JSON: ${err.message}` });
+    }
+  }
+
+  const key = (obj) => `${obj.type}/${obj.id}`;
+  const byKey = R.groupBy(({ obj }) => key(obj), objects);
+  for (const [id, copies] of Object.entries(byKey)) {
+    if (copies.length > 1) {
+      for (const { file } of copies) {
+        problems.push({ file, problem: `duplicate object ${id}` });
+      }
+    }
+  }
+
+  for (const { file, obj } of objects) {
+    for (const ref of obj.references || []) {
+      if (!byKey[key(ref)]) {
+        problems.push({
+          file,
+          problem: `dangling reference ${key(ref)} (${ref.name})`,
+        });
+      }
+    }
+  }
+
+  return problems;
+}
+
+/* Files left behind when an object moved to another file, e.g. after it was
+ * renamed. names is the .kibob-files map of type/id to file name; objects it
+ * has no entry for were added by hand and are never orphans.
+ * Returns an array of { file, key, current }.
+ */
+function orphanedFiles(files, names) {
+  const orphans = [];
+  for (const { file, text } of files) {
+    try {
+      const obj = JSON.parse(text);
+      const key = `${obj.type}/${obj.id}`;
+      if (names[key] && names[key] !== file) {
+        orphans.push({ file, key, current: names[key] });
+      }
+    } catch (err) {
+      // unparseable files are reported by validateFiles
+    }
+  }
+  return orphans;
+}
+
+module.exports = { orphanedFiles, validateFiles };